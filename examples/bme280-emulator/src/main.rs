@@ -1,15 +1,14 @@
 #![no_main]
 #![no_std]
 #![feature(type_alias_impl_trait)]
-#![feature(async_fn_in_trait)]
-
-use cortex_m::peripheral::NVIC;
 
 use defmt_rtt as _;
 use embassy_executor::Spawner;
 use embassy_stm32::{self as _, interrupt, time::Hertz};
 use panic_probe as _;
-use stm32_async_i2c_slave::{handle_error_interrupt, handle_event_interrupt, Bridge, I2CSlave};
+use stm32_async_i2c_slave::{
+    handle_error_interrupt, handle_event_interrupt, Bridge, Config, I2CSlave,
+};
 
 static I2C_BRIDGE: Bridge<embassy_stm32::peripherals::I2C1, 32, 32, 32> = Bridge::new();
 
@@ -89,15 +88,11 @@ async fn main_task(_spawner: Spawner) {
         peripherals.PB7,
         Hertz(100_000),
         0x76,
+        Config::default(),
     );
 
-    unsafe {
-        NVIC::unmask(interrupt::I2C1_ER);
-        NVIC::unmask(interrupt::I2C1_EV);
-    }
+    unsafe { slave.enable_interrupts() };
 
-    let mut last_reading_reg: Option<Bme280Registers> = None;
-    let mut reading_reg: Option<Bme280Registers> = None;
     let mut buf = [0u8; 2];
 
     loop {
@@ -112,12 +107,7 @@ async fn main_task(_spawner: Spawner) {
                     defmt::info!("Received: {}", buf);
 
                     match size {
-                        0 => {}
-                        1 => {
-                            reading_reg.replace(
-                                Bme280Registers::try_from(buf[0]).expect("Unknown register number"),
-                            );
-                        }
+                        0 | 1 => {}
                         2 => {
                             let regnum = buf[0];
                             if let Ok(reg) = Bme280Registers::try_from(regnum) {
@@ -148,14 +138,12 @@ async fn main_task(_spawner: Spawner) {
                         _ => panic!("Unexpected"),
                     }
                 }
-                ev @ Control::TxEmpty { initial, .. } => {
-                    let reg = if initial {
-                        last_reading_reg = reading_reg;
-                        reading_reg.take()
-                    } else {
-                        last_reading_reg
-                    };
-                    let reg = reg.expect("No reading reg set.");
+                ev @ Control::TxEmpty { .. } => {
+                    let mut last = [0u8; 2];
+                    let size =
+                        critical_section::with(|cs| slave.last_write(cs, &mut last).unwrap());
+                    let regnum = *last[..size].first().expect("No reading reg set.");
+                    let reg = Bme280Registers::try_from(regnum).expect("Unknown register number");
                     defmt::info!("Reg: {}. Ev: {}", reg, ev);
 
                     let buf: &[u8] = match reg {
@@ -189,7 +177,9 @@ async fn main_task(_spawner: Spawner) {
                     defmt::info!("Writing {} bytes.", buf.len());
                     slave.write(buf);
                 }
+                _ => {}
             },
+            Ok(_) => {}
             Err(fail) => {
                 defmt::error!("Fail: {}", fail);
                 break;
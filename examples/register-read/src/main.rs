@@ -0,0 +1,91 @@
+#![no_main]
+#![no_std]
+#![feature(type_alias_impl_trait)]
+
+use defmt_rtt as _;
+use embassy_executor::Spawner;
+use embassy_stm32::{self as _, interrupt, time::Hertz};
+use panic_probe as _;
+use stm32_async_i2c_slave::{
+    handle_error_interrupt, handle_event_interrupt, Bridge, Config, I2CSlave,
+};
+
+// A write can fill the whole receive buffer, `buf` below has to take all of it.
+const RX_BUFFER_SIZE: usize = 32;
+
+static I2C_BRIDGE: Bridge<embassy_stm32::peripherals::I2C1, 32, 32, RX_BUFFER_SIZE> = Bridge::new();
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn I2C1_EV() {
+    handle_event_interrupt(&I2C_BRIDGE);
+}
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn I2C1_ER() {
+    handle_error_interrupt(&I2C_BRIDGE);
+}
+
+const REGISTERS_COUNT: usize = 16;
+
+// Emulates a device with a small register file, accessed the SMBus way:
+// - write: START, addr+W, index, data..., STOP
+// - read:  START, addr+W, index, Sr, addr+R, data..., NACK, STOP
+#[embassy_executor::main]
+async fn main_task(_spawner: Spawner) {
+    defmt::info!("Start!");
+
+    let peripherals = embassy_stm32::init(Default::default());
+
+    let slave = I2CSlave::new(
+        peripherals.I2C1,
+        &I2C_BRIDGE,
+        peripherals.PB6,
+        peripherals.PB7,
+        Hertz(100_000),
+        0x50,
+        Config::default(),
+    );
+
+    unsafe { slave.enable_interrupts() };
+
+    let mut registers = [0u8; REGISTERS_COUNT];
+    let mut index = 0usize;
+    let mut buf = [0u8; RX_BUFFER_SIZE];
+
+    loop {
+        use stm32_async_i2c_slave::{Control, Event};
+
+        match slave.listen().await {
+            Ok(Event::Notification(n)) => defmt::info!("Notification: {}", n),
+            Ok(Event::Control(Control::Received { write, .. })) => {
+                let size = critical_section::with(|cs| slave.read(cs, &mut buf).unwrap());
+
+                if let Some((&idx, data)) = buf[..size].split_first() {
+                    index = idx as usize % REGISTERS_COUNT;
+
+                    let end = core::cmp::min(index + data.len(), REGISTERS_COUNT);
+                    registers[index..end].copy_from_slice(&data[..end - index]);
+                }
+
+                // `write` is set when the master turned the transaction around with a
+                // repeated start, i.e. the index is followed by a read.
+                defmt::info!("Index: {}, read follows: {}", index, write);
+            }
+            Ok(Event::Control(Control::TxEmpty { initial, .. })) => {
+                if initial {
+                    slave.write(&registers[index..]);
+                } else {
+                    // Reading past the last register.
+                    slave.write(&[0xFF]);
+                }
+            }
+            Ok(_) => {}
+            Err(fail) => {
+                defmt::error!("Fail: {}", fail);
+                break;
+            }
+        }
+    }
+}
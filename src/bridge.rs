@@ -1,23 +1,52 @@
-use core::cell::RefCell;
+#[cfg(feature = "stats")]
+use core::sync::atomic::AtomicU32;
+use core::{
+    any::Any,
+    cell::{Cell, RefCell},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{ready, Context, Poll},
+};
 
 use critical_section::{CriticalSection, Mutex};
 use embassy_stm32::i2c;
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
+#[cfg(feature = "async-write")]
+use embassy_sync::signal::Signal;
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, RawMutex},
+    channel,
+};
+#[cfg(any(feature = "history", feature = "trace", feature = "frames"))]
 use heapless::Deque;
+#[cfg(feature = "trace")]
+use heapless::Vec;
 
+#[cfg(feature = "dma")]
+use super::interrupts::{rx_dma_channel, tx_dma_channel};
+#[cfg(feature = "trace")]
+use super::Direction;
+#[cfg(any(feature = "dump", feature = "stats"))]
+use super::Notification;
 use super::{
+    bridge_core::BridgeCore,
     interrupts::InterruptBridge,
-    receive_buffer::ReceiveBuffer,
-    send_buffer::SendBuffer,
+    receive_buffer::{ReceiveBuffer, RxSink},
+    register_map::{RegisterRollover, RegisterSource},
+    send_buffer::{SendBuffer, TxSource},
     slave::I2cBridge,
-    state_holder::StateHolder,
     tx_lock::{TxLock, TxLockType},
-    Error, Event, Notification, Reason, State,
+    Control, Error, Event, I2CError, MatchedAddress, Mode, Reason, ReceivedOn, State, Underrun,
+    INLINE_RECEIVED_SIZE,
 };
 
 pub const STATES_HISTORY_SIZE: usize = 5;
+#[cfg(feature = "history")]
 pub const EVENTS_HISTORY_SIZE: usize = 5;
+#[cfg(feature = "trace")]
+pub const TRACE_SIZE: usize = 64;
+#[cfg(feature = "frames")]
+pub const FRAMES_QUEUE_SIZE: usize = 4;
 
+#[cfg(feature = "dump")]
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct StateDump {
@@ -26,24 +55,157 @@ pub struct StateDump {
     pub event_history: [Event; EVENTS_HISTORY_SIZE],
 }
 
-pub type InterruptChannel<const SZ: usize> =
-    channel::Channel<CriticalSectionRawMutex, Result<Event, Error>, SZ>;
+/// Cycles spent in the interrupt handlers, see [`Bridge::isr_timing`].
+#[cfg(feature = "profiling")]
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct IsrTiming {
+    pub min: u32,
+    pub max: u32,
+    pub last: u32,
+}
+
+/// Transaction counters, see [`Bridge::stats`].
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StatsSnapshot {
+    pub writes: u32,
+    pub reads: u32,
+    pub bytes_received: u32,
+    pub bytes_sent: u32,
+    pub errors: u32,
+    pub recovered: u32,
+    /// Interrupts that found no flag to handle.
+    pub spurious_interrupts: u32,
+}
+
+#[cfg(feature = "stats")]
+struct Stats {
+    writes: AtomicU32,
+    reads: AtomicU32,
+    bytes_received: AtomicU32,
+    bytes_sent: AtomicU32,
+    errors: AtomicU32,
+    recovered: AtomicU32,
+    spurious_interrupts: AtomicU32,
+}
+
+#[cfg(feature = "stats")]
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            writes: AtomicU32::new(0),
+            reads: AtomicU32::new(0),
+            bytes_received: AtomicU32::new(0),
+            bytes_sent: AtomicU32::new(0),
+            errors: AtomicU32::new(0),
+            recovered: AtomicU32::new(0),
+            spurious_interrupts: AtomicU32::new(0),
+        }
+    }
+
+    fn snapshot(&self, read: impl Fn(&AtomicU32) -> u32) -> StatsSnapshot {
+        StatsSnapshot {
+            writes: read(&self.writes),
+            reads: read(&self.reads),
+            bytes_received: read(&self.bytes_received),
+            bytes_sent: read(&self.bytes_sent),
+            errors: read(&self.errors),
+            recovered: read(&self.recovered),
+            spurious_interrupts: read(&self.spurious_interrupts),
+        }
+    }
+}
+
+pub type InterruptChannel<M, const SZ: usize> = channel::Channel<M, Result<Event, Error>, SZ>;
 
+/// Shared state between the interrupt handlers and the [`I2CSlave`](crate::I2CSlave).
+///
+/// `M` is the mutex guarding the event channel. The bridge is accessed from the interrupt
+/// handlers, so it has to stay `Sync` to be put in a `static`: any other mutex must be safe
+/// to lock from interrupt context, which `CriticalSectionRawMutex` always is.
+///
+/// None of the sizes may be zero, which is checked at compile time. `CHSIZE` should fit the
+/// events of a whole transaction, a register read with repeated start produces four, so 4 to 8
+/// is a sensible minimum. `TXBUFSIZE` and `RXBUFSIZE` bound the longest response and the
+/// longest write that can be handled in one go. The send buffer is allocated twice, once
+/// more for [`I2CSlave::stage_tx`](crate::I2CSlave::stage_tx), and with the `smbus` feature a
+/// third time, to keep the response written before an alert response.
 pub struct Bridge<
     T: i2c::Instance,
     const CHSIZE: usize,
     const TXBUFSIZE: usize,
     const RXBUFSIZE: usize,
+    M: RawMutex + 'static = CriticalSectionRawMutex,
+    S: TxSource = SendBuffer<TXBUFSIZE>,
+    R: RxSink = ReceiveBuffer<RXBUFSIZE>,
 > {
-    channel: InterruptChannel<CHSIZE>,
+    channel: InterruptChannel<M, CHSIZE>,
+    external_channel: Option<&'static InterruptChannel<M, CHSIZE>>,
+    channel_len: AtomicUsize,
+    #[cfg(feature = "no-panic")]
+    channel_overflow: AtomicBool,
 
-    tx_lock: TxLock<T>,
-    send_buffer: Mutex<RefCell<SendBuffer<TXBUFSIZE>>>,
+    core: BridgeCore<S, R, TxLock<T>>,
+    #[cfg(feature = "async-write")]
+    tx_drained: Signal<M, ()>,
+    #[cfg(feature = "dma")]
+    tx_dma: AtomicBool,
+    #[cfg(feature = "dma")]
+    tx_dma_len: AtomicUsize,
+    #[cfg(feature = "dma")]
+    rx_dma: AtomicBool,
+    #[cfg(feature = "dma")]
+    rx_dma_len: AtomicUsize,
 
-    receive_buffer: Mutex<RefCell<ReceiveBuffer<RXBUFSIZE>>>,
+    #[cfg(feature = "frames")]
+    frames: Mutex<RefCell<Deque<R, FRAMES_QUEUE_SIZE>>>,
+    #[cfg(feature = "frames")]
+    queue_frames: bool,
 
-    state_holder: StateHolder<STATES_HISTORY_SIZE>,
+    #[cfg(feature = "history")]
     events_history: Mutex<RefCell<Deque<Event, EVENTS_HISTORY_SIZE>>>,
+    #[cfg(feature = "trace")]
+    trace: Mutex<RefCell<Deque<(Direction, u8), TRACE_SIZE>>>,
+
+    mode: Mode,
+    received_on: ReceivedOn,
+    underrun: Underrun,
+
+    stop_hook: Option<fn()>,
+    addr_hook: Option<fn(bool, bool) -> bool>,
+
+    register_map: Option<&'static dyn RegisterSource>,
+    register_index: Mutex<Cell<usize>>,
+    register_pos: Mutex<Cell<usize>>,
+    register_rollover: RegisterRollover,
+
+    recoverable_errors: u16,
+
+    context: Option<&'static (dyn Any + Send + Sync)>,
+
+    #[cfg(feature = "profiling")]
+    isr_timing: Mutex<Cell<IsrTiming>>,
+    #[cfg(feature = "stats")]
+    stats: Stats,
+    disable_on_error: AtomicBool,
+    responsive: AtomicBool,
+
+    coalesce_received: bool,
+    general_call_control: bool,
+    inline_received: bool,
+    coalescing: Mutex<Cell<Coalescing>>,
+}
+
+/// Sequence numbers of the events going through the channel, to tell whether the last queued
+/// `Received` is still there to merge into.
+#[derive(Clone, Copy)]
+struct Coalescing {
+    sent: usize,
+    received: usize,
+    tail_received: bool,
+    latest: Option<(usize, Event)>,
 }
 
 #[cfg(feature = "dump")]
@@ -56,26 +218,481 @@ fn deque_into_array<T: Copy, const N: usize>(d: &Deque<T, N>, arr: &mut [T; N])
     arr[s + a.len()..].copy_from_slice(b);
 }
 
-impl<T: i2c::Instance, const CHSIZE: usize, const TXBUFSIZE: usize, const RXBUFSIZE: usize>
-    Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE>
+// `recoverable_errors` has a bit for each `I2CError`, the last variant has to fit.
+const _: () = assert!((I2CError::Underrun as u32) < u16::BITS);
+
+impl<
+        T: i2c::Instance,
+        const CHSIZE: usize,
+        const TXBUFSIZE: usize,
+        const RXBUFSIZE: usize,
+        M: RawMutex + 'static,
+        S: TxSource,
+        R: RxSink,
+    > Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE, M, S, R>
 {
+    const NONZERO_SIZES: () = {
+        assert!(CHSIZE > 0, "Bridge channel size must not be zero");
+        assert!(TXBUFSIZE > 0, "Bridge send buffer size must not be zero");
+        assert!(RXBUFSIZE > 0, "Bridge receive buffer size must not be zero");
+    };
+
     pub const fn new() -> Self {
+        let () = Self::NONZERO_SIZES;
+
         Self {
             channel: InterruptChannel::new(),
-            tx_lock: TxLock::new(),
-            send_buffer: Mutex::new(RefCell::new(SendBuffer::new())),
-            receive_buffer: Mutex::new(RefCell::new(ReceiveBuffer::new())),
-            state_holder: StateHolder::new(),
+            external_channel: None,
+            channel_len: AtomicUsize::new(0),
+            #[cfg(feature = "no-panic")]
+            channel_overflow: AtomicBool::new(false),
+            core: BridgeCore::new(TxLock::new()),
+            #[cfg(feature = "async-write")]
+            tx_drained: Signal::new(),
+            #[cfg(feature = "dma")]
+            tx_dma: AtomicBool::new(false),
+            #[cfg(feature = "dma")]
+            tx_dma_len: AtomicUsize::new(0),
+            #[cfg(feature = "dma")]
+            rx_dma: AtomicBool::new(false),
+            #[cfg(feature = "dma")]
+            rx_dma_len: AtomicUsize::new(0),
+            #[cfg(feature = "frames")]
+            frames: Mutex::new(RefCell::new(Deque::new())),
+            #[cfg(feature = "frames")]
+            queue_frames: false,
+            #[cfg(feature = "history")]
             events_history: Mutex::new(RefCell::new(Deque::new())),
+            #[cfg(feature = "trace")]
+            trace: Mutex::new(RefCell::new(Deque::new())),
+            mode: Mode::Buffered,
+            received_on: ReceivedOn::Both,
+            underrun: Underrun::Stretch,
+            stop_hook: None,
+            addr_hook: None,
+            register_map: None,
+            register_index: Mutex::new(Cell::new(0)),
+            register_pos: Mutex::new(Cell::new(0)),
+            register_rollover: RegisterRollover::Stop,
+            #[cfg(feature = "smbus")]
+            recoverable_errors: 1 << I2CError::Timeout as u16,
+            #[cfg(not(feature = "smbus"))]
+            recoverable_errors: 0,
+            context: None,
+            #[cfg(feature = "profiling")]
+            isr_timing: Mutex::new(Cell::new(IsrTiming {
+                min: u32::MAX,
+                max: 0,
+                last: 0,
+            })),
+            #[cfg(feature = "stats")]
+            stats: Stats::new(),
+            disable_on_error: AtomicBool::new(true),
+            responsive: AtomicBool::new(true),
+            coalesce_received: false,
+            general_call_control: false,
+            inline_received: false,
+            coalescing: Mutex::new(Cell::new(Coalescing {
+                sent: 0,
+                received: 0,
+                tail_received: false,
+                latest: None,
+            })),
         }
     }
 
+    /// Selects how data is exchanged, see [`Mode`]. The default is [`Mode::Buffered`].
+    ///
+    /// With [`Mode::RawByte`] every byte is an event, `CHSIZE` has to cover the bytes the
+    /// consumer may fall behind by.
+    pub const fn with_mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Selects when a write emits `Control::Received`, see [`ReceivedOn`]. The default is
+    /// [`ReceivedOn::Both`].
+    ///
+    /// At a suppressed boundary the buffered write is copied for [`I2cBridge::last_write`]
+    /// and the register index, then dropped from the receive buffer, so the next write starts
+    /// empty. With [`ReceivedOn::Restart`] the events of a full receive buffer are suppressed
+    /// too, as they are not followed by a read, and their bytes are dropped the same way.
+    pub const fn with_received_on(mut self, received_on: ReceivedOn) -> Self {
+        self.received_on = received_on;
+        self
+    }
+
+    /// Selects what a read gets when the send buffer runs empty, see [`Underrun`]. The
+    /// default is [`Underrun::Stretch`].
+    ///
+    /// Otherwise the read goes on without stretching. `Control::TxEmpty` is only emitted for
+    /// the first byte of a read, the consumer's answer is sent from the next byte on. Padding
+    /// is not counted in `Notification::Sent`.
+    pub const fn with_underrun(mut self, underrun: Underrun) -> Self {
+        self.underrun = underrun;
+        self
+    }
+
+    /// Sets a hook called with the old and the new state on every state change.
+    ///
+    /// It runs in the interrupt handler, so it must be short.
+    pub const fn with_state_hook(mut self, hook: fn(State, State)) -> Self {
+        self.core.state_hook = Some(hook);
+        self
+    }
+
+    /// Sets a hook called from the interrupt handler as soon as a STOP is detected, before
+    /// the events of the transaction are emitted.
+    pub const fn with_stop_hook(mut self, hook: fn()) -> Self {
+        self.stop_hook = Some(hook);
+        self
+    }
+
+    /// Sets a hook called from the interrupt handler at every address match with the
+    /// direction, `true` for a read, and whether it is a general call. Returning `false`
+    /// rejects the transaction: all data bytes of a write are NACKed.
+    ///
+    /// The hardware has already ACKed the address when the hook runs, so a rejected read
+    /// still takes place. The master gets the fill byte of
+    /// [`Bridge::with_underrun`], `0xFF` when stretching, for every byte. No `TxEmpty` is
+    /// emitted and the send buffer is left for the next read.
+    pub const fn with_addr_hook(mut self, hook: fn(bool, bool) -> bool) -> Self {
+        self.addr_hook = Some(hook);
+        self
+    }
+
+    /// Serves reads from `map` instead of emitting `TxEmpty`.
+    ///
+    /// The first byte of each write selects the register index, a following read returns
+    /// the registers from that index on. `TxEmpty` is only emitted once the read runs past
+    /// the end of the map. Writes are still delivered through `Received` as usual.
+    pub const fn with_register_map(mut self, map: &'static dyn RegisterSource) -> Self {
+        self.register_map = Some(map);
+        self
+    }
+
+    /// Sets what a read served from the register map does past the last register, the
+    /// default is [`RegisterRollover::Stop`].
+    pub const fn with_register_rollover(mut self, rollover: RegisterRollover) -> Self {
+        self.register_rollover = rollover;
+        self
+    }
+
+    /// Holds the next transaction after a `Received` until the consumer calls
+    /// [`I2CSlave::ack_processed`](crate::I2CSlave::ack_processed).
+    ///
+    /// The address match is left pending, which stretches SCL, so a slow consumer slows the
+    /// master down instead of overflowing the receive buffer or the channel. A repeated start
+    /// right after a write is not held, the read that follows is served as usual.
+    pub const fn with_backpressure(mut self) -> Self {
+        self.core.backpressure = true;
+        self
+    }
+
+    /// Merges a `Received` into the one before it while that one is still queued, the consumer
+    /// gets it with the latest size. Eases the channel on writes that raise `Received` often,
+    /// like with [`Bridge::with_rx_high_water`].
+    pub const fn with_coalesced_received(mut self) -> Self {
+        self.coalesce_received = true;
+        self
+    }
+
+    /// Emits `Control::GeneralCall` instead of `Control::Received` for writes to the general
+    /// call address, so they reach a separate arm of the consumer, and consumers only matching
+    /// `Received`, like [`RegisterSlave`](crate::RegisterSlave), leave them alone.
+    ///
+    /// The data is in the receive buffer as for any write, its first byte can be decoded with
+    /// [`GeneralCallCommand`](crate::GeneralCallCommand).
+    pub const fn with_general_call_control(mut self) -> Self {
+        self.general_call_control = true;
+        self
+    }
+
+    /// Emits writes of up to [`INLINE_RECEIVED_SIZE`](crate::INLINE_RECEIVED_SIZE) bytes as
+    /// `Control::ReceivedInline`, which carries the data, instead of `Control::Received`.
+    ///
+    /// The data is moved out of the receive buffer into the event, there is nothing to read
+    /// afterwards. Longer writes are emitted as `Received` as before. Helpers waiting for
+    /// `Received`, like [`I2CSlave::read_transaction`](crate::I2CSlave::read_transaction),
+    /// miss the short writes with this set.
+    pub const fn with_inline_received(mut self) -> Self {
+        self.inline_received = true;
+        self
+    }
+
+    /// Moves every write into a queue of `FRAMES_QUEUE_SIZE` writes at its end, at the STOP
+    /// or the repeated start, to be taken with
+    /// [`I2CSlave::read_frame`](crate::I2CSlave::read_frame).
+    ///
+    /// Writes coming in before the consumer gets to them keep their boundaries, but the
+    /// receive buffer reads empty once `Received` has been emitted. A `Received` of a
+    /// high-water mark or a full buffer leaves the data in place, the frame takes what is
+    /// left of it at the end of the write. With the queue full the write stays in the receive
+    /// buffer and is queued together with the next one.
+    #[cfg(feature = "frames")]
+    pub const fn with_frames(mut self) -> Self {
+        self.queue_frames = true;
+        self
+    }
+
+    /// Sends the events into `channel` instead of the channel owned by the bridge, to merge
+    /// them into an application-wide event queue.
+    ///
+    /// The bridge still receives from `channel` when asked to. Events taken off it elsewhere
+    /// are not seen by [`Bridge::channel_len`] and [`Bridge::with_coalesced_received`].
+    pub const fn with_channel(mut self, channel: &'static InterruptChannel<M, CHSIZE>) -> Self {
+        self.external_channel = Some(channel);
+        self
+    }
+
+    /// Treats `err` as recoverable instead of failing.
+    ///
+    /// The flag is cleared, the current transfer is dropped and the bridge returns to `Idle`
+    /// with the peripheral left enabled, reporting it as `Notification::Recovered`.
+    ///
+    /// On a multi-master bus `ArbitrationLoss` is part of normal operation and is the usual
+    /// candidate. Keep in mind that the other master keeps the bus, so the slave may be
+    /// addressed again right away and the dropped transfer is not retried by the slave.
+    ///
+    /// `Underrun`, a read running faster than the send buffer is filled with
+    /// [`Config::no_stretch`](crate::Config::no_stretch), is the exception: only the send
+    /// buffer is reset and the read goes on.
+    ///
+    /// With the `smbus` feature `Timeout` is recoverable from the start. The feature puts the
+    /// peripheral in SMBus device mode, in which the hardware resets the communication and
+    /// releases the lines when SCL stays low for more than 25 ms, or the slave stretches it
+    /// for more than 25 ms in total. Without it the timeout is off. The timeout cannot be
+    /// enabled or set up on its own: the F1 peripheral has no timeout register, the 25 ms
+    /// limits are fixed.
+    pub const fn with_recoverable(mut self, err: I2CError) -> Self {
+        self.recoverable_errors |= 1 << err as u16;
+        self
+    }
+
+    /// Attaches application data, to be reached through [`Bridge::context`] from code running
+    /// next to the interrupt handlers, like the state hook or a [`RegisterSource`].
+    ///
+    /// The bridge only hands out shared references, anything mutable in `ctx` has to be
+    /// behind a critical section mutex or an atomic.
+    pub const fn with_context(mut self, ctx: &'static (dyn Any + Send + Sync)) -> Self {
+        self.context = Some(ctx);
+        self
+    }
+
+    /// The data attached with [`Bridge::with_context`], if it is a `C`.
+    pub fn context<C: Any>(&self) -> Option<&'static C> {
+        self.context?.downcast_ref()
+    }
+
+    /// Sets whether received bytes are acknowledged.
+    ///
+    /// The hardware ACKs each byte as soon as it has been shifted in, before the `Rx` branch
+    /// of the event interrupt stores it, so the setting applies to the bytes arriving after
+    /// the call. To NACK the last byte of a bounded write, clear it while the previous byte
+    /// is being handled. With ACK cleared the own address is NACKed as well, so the event
+    /// interrupt sets it again on the STOP that ends the transaction, the first point after
+    /// which no more bytes of it can arrive.
+    pub fn set_ack(&self, ack: bool) {
+        T::regs().cr1().modify(|w| w.set_ack(ack));
+    }
+
     fn disable_peripheral() {
         T::regs().cr1().modify(|w| w.set_pe(false));
     }
 
+    fn disable_interrupts() {
+        T::regs().cr2().modify(|w| {
+            w.set_itbufen(false);
+            w.set_itevten(false);
+            w.set_iterren(false);
+        });
+    }
+
     fn send_channel(&self, result: Result<Event, Error>) {
-        self.channel.try_send(result).expect("Channel is full")
+        if !self.coalesce_received {
+            self.enqueue(result);
+            return;
+        }
+
+        critical_section::with(|cs| {
+            let cell = self.coalescing.borrow(cs);
+            let mut c = cell.get();
+
+            match result {
+                Ok(event @ Event::Control(Control::Received { .. }))
+                    if c.tail_received && c.received != c.sent =>
+                {
+                    c.latest = Some((c.sent.wrapping_sub(1), event));
+                }
+                _ if self.enqueue(result) => {
+                    c.sent = c.sent.wrapping_add(1);
+                    c.tail_received =
+                        matches!(result, Ok(Event::Control(Control::Received { .. })));
+                }
+                _ => {}
+            }
+
+            cell.set(c);
+        });
+    }
+
+    /// Returns `false` if the event was dropped.
+    fn enqueue(&self, result: Result<Event, Error>) -> bool {
+        #[cfg(not(feature = "no-panic"))]
+        if let Err(channel::TrySendError::Full(result)) = self.channel().try_send(result) {
+            error!(
+                "Channel is full: state {:?}, {} of {} queued, dropping {:?}",
+                self.get_state(),
+                self.channel_len(),
+                CHSIZE,
+                result
+            );
+
+            panic!(
+                "Channel is full: state {:?}, {} of {} queued, dropping {:?}",
+                self.get_state(),
+                self.channel_len(),
+                CHSIZE,
+                result
+            );
+        }
+        #[cfg(feature = "no-panic")]
+        if self.channel().try_send(result).is_err() {
+            self.channel_overflow.store(true, Ordering::SeqCst);
+            return false;
+        }
+
+        self.channel_len.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn channel(&self) -> &InterruptChannel<M, CHSIZE> {
+        self.external_channel.unwrap_or(&self.channel)
+    }
+
+    fn received_channel(&self, r: &mut Result<Event, Error>) {
+        self.channel_len.fetch_sub(1, Ordering::SeqCst);
+
+        if let Some(event) = self.take_coalesced() {
+            *r = Ok(event);
+        }
+    }
+
+    /// Counts an event as received, returns the `Received` merged into it, if any.
+    fn take_coalesced(&self) -> Option<Event> {
+        if !self.coalesce_received {
+            return None;
+        }
+
+        critical_section::with(|cs| {
+            let cell = self.coalescing.borrow(cs);
+            let mut c = cell.get();
+            let seq = c.received;
+            c.received = c.received.wrapping_add(1);
+
+            let merged = match c.latest {
+                Some((latest_seq, event)) if latest_seq == seq => {
+                    c.latest = None;
+                    Some(event)
+                }
+                _ => None,
+            };
+
+            cell.set(c);
+            merged
+        })
+    }
+
+    /// Number of events queued and not yet received by the consumer.
+    pub fn channel_len(&self) -> usize {
+        self.channel_len.load(Ordering::SeqCst)
+    }
+
+    pub const fn channel_capacity() -> usize {
+        CHSIZE
+    }
+
+    /// RAM taken by a bridge `static` with these parameters, for a compile-time budget check
+    /// like `const _: () = assert!(MyBridge::size_bytes() <= 1024);`.
+    ///
+    /// With the default buffers it comes down to `CHSIZE` events, the send and the staged
+    /// buffer of `TXBUFSIZE` bytes, a third one with the `smbus` feature, the receive buffer
+    /// and the copy of the last write of `RXBUFSIZE` bytes, and the histories of the enabled
+    /// features.
+    pub const fn size_bytes() -> usize {
+        core::mem::size_of::<Self>()
+    }
+
+    /// Returns `true` if events were dropped on a full channel since the last call.
+    #[cfg(feature = "no-panic")]
+    pub fn take_channel_overflow(&self) -> bool {
+        self.channel_overflow.swap(false, Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_byte(&self, cs: CriticalSection, direction: Direction, byte: u8) {
+        let mut t = self.trace.borrow_ref_mut(cs);
+        if t.is_full() {
+            t.pop_front();
+        }
+        let _ = t.push_back((direction, byte));
+    }
+
+    /// The last `TRACE_SIZE` bytes received and sent, oldest first.
+    ///
+    /// Only the bytes stored in the receive buffer or taken from the send buffer are
+    /// recorded, not the addresses.
+    #[cfg(feature = "trace")]
+    pub fn trace_snapshot(&self) -> Vec<(Direction, u8), TRACE_SIZE> {
+        critical_section::with(|cs| self.trace.borrow_ref(cs).iter().copied().collect())
+    }
+
+    /// Minimum, maximum and last number of CPU cycles spent in one call of either interrupt
+    /// handler, `min` is `u32::MAX` until the first call.
+    ///
+    /// The cycles are counted with the DWT cycle counter, which has to be enabled by the
+    /// application.
+    #[cfg(feature = "profiling")]
+    pub fn isr_timing(&self) -> IsrTiming {
+        critical_section::with(|cs| self.isr_timing.borrow(cs).get())
+    }
+
+    /// Counts of transactions, bytes and errors since start or the last [`Bridge::take_stats`].
+    ///
+    /// Writes and reads are counted at the address match, so a write followed by a read with
+    /// repeated start counts as one of each.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot(|c| c.load(Ordering::Relaxed))
+    }
+
+    /// Like [`Bridge::stats`], but zeroes the counters. Each counter is swapped on its own, so
+    /// an increment from the interrupt handler is never lost, it lands in either snapshot.
+    #[cfg(feature = "stats")]
+    pub fn take_stats(&self) -> StatsSnapshot {
+        self.stats.snapshot(|c| c.swap(0, Ordering::Relaxed))
+    }
+
+    #[cfg(feature = "stats")]
+    fn count(counter: &AtomicU32, n: usize) {
+        counter.fetch_add(n as u32, Ordering::Relaxed);
+    }
+
+    /// Copies the most recent events into `out`, newest first, returns how many were copied.
+    ///
+    /// The bridge remembers the last `EVENTS_HISTORY_SIZE` events sent to the consumer, errors
+    /// are not part of it.
+    #[cfg(feature = "history")]
+    pub fn recent_events(&self, out: &mut [Event]) -> usize {
+        critical_section::with(|cs| {
+            let h = self.events_history.borrow_ref(cs);
+            let mut n = 0;
+            for (dst, event) in out.iter_mut().zip(h.iter().rev()) {
+                *dst = *event;
+                n += 1;
+            }
+            n
+        })
     }
 
     #[cfg(feature = "dump")]
@@ -85,7 +702,7 @@ impl<T: i2c::Instance, const CHSIZE: usize, const TXBUFSIZE: usize, const RXBUFS
             [Event::Notification(Notification::Stop); EVENTS_HISTORY_SIZE];
 
         critical_section::with(|cs| {
-            let states_deque = self.state_holder.get_history(cs);
+            let states_deque = self.core.state_holder.get_history(cs);
             deque_into_array(&states_deque, &mut states);
 
             let events_deque = self.events_history.borrow_ref(cs);
@@ -100,88 +717,569 @@ impl<T: i2c::Instance, const CHSIZE: usize, const TXBUFSIZE: usize, const RXBUFS
     }
 }
 
-impl<T: i2c::Instance, const CHSIZE: usize, const TXBUFSIZE: usize, const RXBUFSIZE: usize>
-    I2cBridge<T> for Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE>
+impl<
+        T: i2c::Instance,
+        const CHSIZE: usize,
+        const TXBUFSIZE: usize,
+        const RXBUFSIZE: usize,
+        M: RawMutex + 'static,
+        S: TxSource,
+    > Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE, M, S, ReceiveBuffer<RXBUFSIZE>>
 {
+    /// Emits `Received` as soon as a write has collected `bytes` bytes, without waiting for
+    /// the STOP, so long writes can be drained while they are still coming in.
+    ///
+    /// It fires once per crossing: after the consumer has read the buffer, the next `bytes`
+    /// bytes trigger it again. The remainder is reported at the STOP as usual.
+    pub const fn with_rx_high_water(mut self, bytes: usize) -> Self {
+        self.core.receive_buffer = Mutex::new(RefCell::new(ReceiveBuffer::with_high_water(bytes)));
+        self
+    }
+}
+
+impl<
+        T: i2c::Instance,
+        const CHSIZE: usize,
+        const TXBUFSIZE: usize,
+        const RXBUFSIZE: usize,
+        M: RawMutex + 'static,
+        S: TxSource,
+        R: RxSink,
+    > I2cBridge<T> for Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE, M, S, R>
+{
+    const TX_CAPACITY: usize = S::CAPACITY;
+
+    const RX_CAPACITY: usize = R::CAPACITY;
+
     async fn receive(&self) -> Result<Event, Error> {
-        self.channel.receive().await
+        let mut r = self.channel().receive().await;
+        self.received_channel(&mut r);
+        r
+    }
+
+    fn try_receive(&self) -> Option<Result<Event, Error>> {
+        let mut r = self.channel().try_receive().ok()?;
+        self.received_channel(&mut r);
+        Some(r)
+    }
+
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Result<Event, Error>> {
+        let mut r = ready!(self.channel().poll_receive(cx));
+        self.received_channel(&mut r);
+        Poll::Ready(r)
+    }
+
+    fn state(&self) -> State {
+        self.core.state()
     }
 
     fn write<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
-        let res = self.send_buffer.borrow_ref_mut(cs).write(buf);
-        self.unlock_tx();
-        res
+        self.core.write(cs, buf)
+    }
+
+    fn clear_tx(&self, cs: CriticalSection) {
+        self.core.clear_tx(cs)
+    }
+
+    fn stage_tx<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        self.core.stage_tx(cs, buf)
+    }
+
+    fn tx_remaining(&self, cs: CriticalSection) -> usize {
+        self.core.tx_remaining(cs)
     }
 
     fn read(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
-        let mut rb = self.receive_buffer.borrow_ref_mut(cs);
-        let r = rb.read(buf);
+        self.core.read(cs, buf)
+    }
+
+    fn read_some(&self, cs: CriticalSection, buf: &mut [u8]) -> usize {
+        self.core.read_some(cs, buf)
+    }
+
+    fn read_with_crc(
+        &self,
+        cs: CriticalSection,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, u8)>, usize> {
+        self.core.read_with_crc(cs, buf)
+    }
+
+    fn last_write(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        self.core.last_write(cs, buf)
+    }
+
+    #[cfg(feature = "frames")]
+    fn read_frame(&self, cs: CriticalSection, buf: &mut [u8]) -> Option<Result<usize, usize>> {
+        let mut frames = self.frames.borrow_ref_mut(cs);
+        let r = frames.front()?.read(buf);
 
         if r.is_ok() {
-            rb.reset();
+            frames.pop_front();
         }
 
-        r
+        Some(r)
+    }
+
+    fn last_pec(&self) -> u8 {
+        self.core.last_pec()
+    }
+
+    fn last_error(&self) -> Option<Error> {
+        self.core.last_error()
+    }
+
+    fn matched_address(&self) -> MatchedAddress {
+        self.core.matched_address()
+    }
+
+    fn ack_processed(&self) {
+        self.core.ack_processed()
+    }
+
+    fn set_disable_on_error(&self, disable: bool) {
+        self.disable_on_error.store(disable, Ordering::SeqCst);
+    }
+
+    fn set_responsive(&self, responsive: bool) {
+        critical_section::with(|_| {
+            self.responsive.store(responsive, Ordering::SeqCst);
+
+            // A transaction under way keeps its ACK, the STOP ending it applies the setting.
+            if responsive || matches!(self.get_state(), State::Idle) {
+                self.set_ack(responsive);
+            }
+        });
+    }
+
+    fn is_responsive(&self) -> bool {
+        self.responsive.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "dma")]
+    fn set_tx_dma(&self, enable: bool) {
+        self.tx_dma.store(enable, Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "dma")]
+    fn set_rx_dma(&self, enable: bool) {
+        self.rx_dma.store(enable, Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "async-write")]
+    async fn wait_txbuf_drained(&self) {
+        self.tx_drained.wait().await
+    }
+
+    #[cfg(feature = "async-write")]
+    fn reset_txbuf_drained(&self) {
+        self.tx_drained.reset();
+    }
+
+    fn reset(&self, cs: CriticalSection) {
+        #[cfg(feature = "frames")]
+        self.frames.borrow_ref_mut(cs).clear();
+        self.core.reset(cs);
+    }
+
+    #[cfg(feature = "history")]
+    fn clear_history(&self, cs: CriticalSection) {
+        self.events_history.borrow_ref_mut(cs).clear();
     }
 }
 
-impl<T: i2c::Instance, const CHSIZE: usize, const TXBUFSIZE: usize, const RXBUFSIZE: usize>
-    InterruptBridge<T> for Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE>
+impl<
+        T: i2c::Instance,
+        const CHSIZE: usize,
+        const TXBUFSIZE: usize,
+        const RXBUFSIZE: usize,
+        M: RawMutex + 'static,
+        S: TxSource,
+        R: RxSink,
+    > InterruptBridge<T> for Bridge<T, CHSIZE, TXBUFSIZE, RXBUFSIZE, M, S, R>
 {
     fn get_state(&self) -> State {
-        self.state_holder.get_state()
+        self.core.state()
+    }
+
+    fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    fn underrun_byte(&self) -> Option<u8> {
+        match self.underrun {
+            Underrun::Stretch => None,
+            Underrun::Fill(byte) => Some(byte),
+            Underrun::RepeatLast => Some(self.core.last_tx_byte.load(Ordering::SeqCst)),
+        }
     }
 
     fn set_state(&self, state: State) {
-        self.state_holder.set_state(state)
+        self.core.set_state(state)
     }
 
     fn fail(&self, reason: Reason) {
-        Self::disable_peripheral();
+        error!("failed in {:?}: {:?}", self.get_state(), reason);
+
+        #[cfg(feature = "stats")]
+        Self::count(&self.stats.errors, 1);
+
+        if self.disable_on_error.load(Ordering::SeqCst) {
+            Self::disable_peripheral();
+        } else {
+            Self::disable_interrupts();
+        }
 
-        self.send_channel(Err(Error {
+        let error = Error {
             #[cfg(feature = "dump")]
             dump: self.dump_state(),
             reason,
-        }));
+        };
+
+        self.core.set_error(error);
+        self.send_channel(Err(error));
     }
 
-    fn notify(&self, event: Event) {
+    fn notify(&self, mut event: Event) {
+        #[cfg(feature = "stats")]
+        match event {
+            Event::Notification(Notification::Addr { tx: false, .. }) => {
+                Self::count(&self.stats.writes, 1)
+            }
+            Event::Notification(Notification::Addr { tx: true, .. }) => {
+                Self::count(&self.stats.reads, 1)
+            }
+            Event::Notification(Notification::Sent { sent }) => {
+                Self::count(&self.stats.bytes_sent, sent)
+            }
+            Event::Notification(Notification::Recovered { .. }) => {
+                Self::count(&self.stats.recovered, 1)
+            }
+            _ => {}
+        }
+
+        #[cfg(feature = "async-write")]
+        if let Event::Control(Control::TxEmpty { .. } | Control::TxByte) = event {
+            self.tx_drained.signal(());
+        }
+
+        let emit = match event {
+            Event::Control(Control::Received { write, .. }) => match self.received_on {
+                ReceivedOn::Both => true,
+                ReceivedOn::Stop => !write,
+                ReceivedOn::Restart => write,
+            },
+            _ => true,
+        };
+
         critical_section::with(|cs| {
-            let mut h = self.events_history.borrow_ref_mut(cs);
-            if h.is_full() {
-                h.pop_front();
+            if let (Some(_), Event::Control(Control::Received { .. })) = (self.register_map, event)
+            {
+                if let Some(index) = self.core.receive_buffer.borrow_ref(cs).first() {
+                    self.register_index.borrow(cs).set(index as usize);
+                }
+            }
+
+            self.core.notify(cs, event, emit);
+
+            match event {
+                Event::Control(Control::Received {
+                    size,
+                    write,
+                    address,
+                }) if emit
+                    && self.inline_received
+                    && size <= INLINE_RECEIVED_SIZE
+                    && !(self.general_call_control && address == MatchedAddress::GeneralCall) =>
+                {
+                    let mut rb = self.core.receive_buffer.borrow_ref_mut(cs);
+                    let mut data = [0; INLINE_RECEIVED_SIZE];
+                    let _ = rb.read(&mut data);
+                    rb.reset();
+
+                    event = Event::Control(Control::ReceivedInline {
+                        data,
+                        len: size as u8,
+                        write,
+                        address,
+                    });
+                }
+                _ => {}
+            }
+
+            #[cfg(feature = "history")]
+            if emit {
+                let mut h = self.events_history.borrow_ref_mut(cs);
+                if h.is_full() {
+                    h.pop_front();
+                }
+                let _ = h.push_back(event);
             }
-            h.push_back(event).unwrap();
         });
 
-        self.send_channel(Ok(event))
+        if !emit {
+            return;
+        }
+
+        match event {
+            Event::Control(Control::Received {
+                size,
+                write,
+                address: MatchedAddress::GeneralCall,
+            }) if self.general_call_control => {
+                self.send_channel(Ok(Event::Control(Control::GeneralCall { size, write })))
+            }
+            event => self.send_channel(Ok(event)),
+        }
     }
 
     fn lock_tx(&self, lock_type: TxLockType) {
-        self.tx_lock.lock(lock_type)
+        self.core.lock_tx(lock_type)
     }
 
     fn unlock_tx(&self) {
-        self.tx_lock.unlock()
+        self.core.unlock_tx()
     }
 
     fn get_rxbuf_size(&self, cs: CriticalSection) -> usize {
-        self.receive_buffer.borrow_ref(cs).get_size()
+        self.core.get_rxbuf_size(cs)
     }
 
     fn write_rxbuf_byte(&self, cs: CriticalSection, byte: u8) -> Result<(), ()> {
-        self.receive_buffer.borrow_ref_mut(cs).write_byte(byte)
+        self.core.write_rxbuf_byte(cs, byte)?;
+
+        #[cfg(feature = "stats")]
+        Self::count(&self.stats.bytes_received, 1);
+
+        #[cfg(feature = "trace")]
+        self.trace_byte(cs, Direction::Rx, byte);
+
+        Ok(())
+    }
+
+    fn take_rxbuf_high_water(&self, cs: CriticalSection) -> bool {
+        self.core.take_rxbuf_high_water(cs)
+    }
+
+    #[cfg(feature = "frames")]
+    fn end_frame(&self, cs: CriticalSection) {
+        let mut rb = self.core.receive_buffer.borrow_ref_mut(cs);
+
+        if !self.queue_frames || rb.get_size() == 0 {
+            return;
+        }
+
+        if self.frames.borrow_ref_mut(cs).push_back(rb.clone()).is_ok() {
+            rb.reset();
+        }
     }
 
     fn pop_txbuf_byte(&self, cs: CriticalSection) -> Option<u8> {
-        self.send_buffer.borrow_ref_mut(cs).next()
+        let byte = self.core.pop_txbuf_byte(cs);
+
+        #[cfg(feature = "trace")]
+        if let Some(byte) = byte {
+            self.trace_byte(cs, Direction::Tx, byte);
+        }
+
+        byte
+    }
+
+    fn promote_txbuf(&self, cs: CriticalSection) {
+        self.core.promote_txbuf(cs)
+    }
+
+    fn fill_txbuf(&self, cs: CriticalSection, initial: bool) -> bool {
+        let Some(map) = self.register_map else {
+            return false;
+        };
+
+        let mut pos = if initial {
+            self.register_index.borrow(cs).get()
+        } else {
+            self.register_pos.borrow(cs).get()
+        };
+
+        let n = self.core.send_buffer.borrow_ref_mut(cs).fill_with(|buf| {
+            match map.read_registers(cs, pos, buf) {
+                0 if pos != 0 => match self.register_rollover {
+                    RegisterRollover::Stop => 0,
+                    RegisterRollover::Wrap => {
+                        pos = 0;
+                        map.read_registers(cs, pos, buf)
+                    }
+                    RegisterRollover::Clamp => {
+                        pos -= 1;
+                        map.read_registers(cs, pos, &mut buf[..1])
+                    }
+                },
+                n => n,
+            }
+        });
+        self.register_pos.borrow(cs).set(pos + n);
+
+        n != 0
+    }
+
+    fn is_txbuf_empty(&self, cs: CriticalSection) -> bool {
+        self.core.is_txbuf_empty(cs)
+    }
+
+    #[cfg(feature = "dma")]
+    fn tx_dma_channel(&self) -> Option<usize> {
+        self.tx_dma.load(Ordering::SeqCst).then(tx_dma_channel::<T>)
+    }
+
+    #[cfg(feature = "dma")]
+    fn start_txbuf_dma(&self, cs: CriticalSection) -> Option<(*const u8, usize)> {
+        let sb = self.core.send_buffer.borrow_ref(cs);
+        let pending = sb.pending();
+        // NDTR is 16 bits wide, the rest is sent from the interrupt handler.
+        let len = core::cmp::min(pending.len(), u16::MAX as usize);
+
+        if len == 0 {
+            return None;
+        }
+
+        self.tx_dma_len.store(len, Ordering::SeqCst);
+        Some((pending.as_ptr(), len))
+    }
+
+    #[cfg(feature = "dma")]
+    fn end_txbuf_dma(&self, cs: CriticalSection, remaining: usize) {
+        let len = self.tx_dma_len.swap(0, Ordering::SeqCst);
+
+        #[cfg(feature = "trace")]
+        for &byte in &self.core.send_buffer.borrow_ref(cs).pending()[..len - remaining] {
+            self.trace_byte(cs, Direction::Tx, byte);
+        }
+
+        self.core.advance_txbuf(cs, len - remaining);
+    }
+
+    #[cfg(feature = "dma")]
+    fn rx_dma_channel(&self) -> Option<usize> {
+        self.rx_dma.load(Ordering::SeqCst).then(rx_dma_channel::<T>)
+    }
+
+    #[cfg(feature = "dma")]
+    fn start_rxbuf_dma(&self, cs: CriticalSection) -> Option<(*mut u8, usize)> {
+        let mut rb = self.core.receive_buffer.borrow_ref_mut(cs);
+        let spare = rb.spare();
+        let len = core::cmp::min(spare.len(), u16::MAX as usize);
+
+        if len == 0 {
+            return None;
+        }
+
+        self.rx_dma_len.store(len, Ordering::SeqCst);
+        Some((spare.as_mut_ptr(), len))
+    }
+
+    #[cfg(feature = "dma")]
+    fn end_rxbuf_dma(&self, cs: CriticalSection, remaining: usize) {
+        let len = self.rx_dma_len.swap(0, Ordering::SeqCst);
+        let mut rb = self.core.receive_buffer.borrow_ref_mut(cs);
+
+        #[cfg(feature = "trace")]
+        for &byte in &rb.spare()[..len - remaining] {
+            self.trace_byte(cs, Direction::Rx, byte);
+        }
+
+        rb.commit(len - remaining);
+
+        if len != remaining {
+            self.core.set_transferred(true);
+        }
+
+        #[cfg(feature = "stats")]
+        Self::count(&self.stats.bytes_received, len - remaining);
     }
 
     fn reset_txbuf(&self, cs: CriticalSection) -> usize {
-        let mut sb = self.send_buffer.borrow_ref_mut(cs);
-        let bytes_left = sb.bytes_sent();
-        sb.reset();
-        bytes_left
+        let sent = self.core.reset_txbuf(cs);
+
+        #[cfg(feature = "async-write")]
+        self.tx_drained.signal(());
+
+        sent
+    }
+
+    fn reset_rxbuf(&self, cs: CriticalSection) {
+        self.core.reset_rxbuf(cs)
+    }
+
+    fn set_pec(&self, pec: u8) {
+        self.core.set_pec(pec)
+    }
+
+    fn get_matched_address(&self) -> MatchedAddress {
+        self.core.matched_address()
+    }
+
+    fn set_matched_address(&self, address: MatchedAddress) {
+        self.core.set_matched_address(address)
+    }
+
+    fn hold_addr(&self) -> bool {
+        self.core.hold_addr()
+    }
+
+    fn set_transferred(&self, transferred: bool) {
+        self.core.set_transferred(transferred)
+    }
+
+    fn transferred(&self) -> bool {
+        self.core.transferred()
+    }
+
+    fn ack_address(&self) -> bool {
+        self.responsive.load(Ordering::SeqCst)
+    }
+
+    fn accept_addr(&self, tx: bool, gencall: bool) -> bool {
+        self.addr_hook.map_or(true, |hook| hook(tx, gencall))
+    }
+
+    fn set_read_refused(&self, refused: bool) {
+        self.core.set_read_refused(refused)
+    }
+
+    fn read_refused(&self) -> bool {
+        self.core.read_refused()
+    }
+
+    fn stop_detected(&self) {
+        if let Some(hook) = self.stop_hook {
+            hook();
+        }
+    }
+
+    #[cfg(feature = "smbus")]
+    fn respond_alert(&self, cs: CriticalSection, address: u8) {
+        self.core.respond_alert(cs, address)
+    }
+
+    fn is_recoverable(&self, err: I2CError) -> bool {
+        self.recoverable_errors & (1 << err as u16) != 0
+    }
+
+    #[cfg(feature = "profiling")]
+    fn record_isr_cycles(&self, cycles: u32) {
+        critical_section::with(|cs| {
+            let timing = self.isr_timing.borrow(cs);
+            let t = timing.get();
+
+            timing.set(IsrTiming {
+                min: t.min.min(cycles),
+                max: t.max.max(cycles),
+                last: cycles,
+            });
+        });
+    }
+
+    #[cfg(feature = "stats")]
+    fn count_spurious_interrupt(&self) {
+        Self::count(&self.stats.spurious_interrupts, 1);
     }
 }
@@ -0,0 +1,395 @@
+use core::{
+    cell::{Cell, RefCell},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
+};
+
+use atomic::Atomic;
+use critical_section::{CriticalSection, Mutex};
+
+use super::{
+    bridge::STATES_HISTORY_SIZE,
+    receive_buffer::RxSink,
+    send_buffer::TxSource,
+    state_holder::StateHolder,
+    tx_lock::{Lock, TxLockType},
+    Control, Error, Event, MatchedAddress, State,
+};
+
+/// The transitions made by the interrupt handler:
+///
+/// - any state to `Idle`, at STOP, on a recovered error and on a reset
+/// - `Idle`, `Rx` and `Nack` to `Rx` or `TxInitial` at an address match, from `Rx` and `Nack`
+///   on a repeated start
+/// - `TxInitial` to `TxRepeated` once the first byte of a read is on its way
+/// - `TxInitial` and `TxRepeated` to `Nack` when the master ends the read
+fn is_legal_transition(old: State, new: State) -> bool {
+    matches!(
+        (old, new),
+        (_, State::Idle)
+            | (
+                State::Idle | State::Rx | State::Nack,
+                State::Rx | State::TxInitial
+            )
+            | (State::TxInitial, State::TxRepeated)
+            | (State::TxInitial | State::TxRepeated, State::Nack)
+    )
+}
+
+/// The buffers and the state shared by [`Bridge`](crate::Bridge) and the mock bridge, without
+/// anything touching the peripheral registers but the TX lock `L`.
+pub(crate) struct BridgeCore<S: TxSource, R: RxSink, L: Lock> {
+    pub(crate) tx_lock: L,
+    pub(crate) send_buffer: Mutex<RefCell<S>>,
+    staged_buffer: Mutex<RefCell<S>>,
+    /// The send buffer set aside while the Alert Response Address is answered.
+    #[cfg(feature = "smbus")]
+    alert_stash: Mutex<RefCell<S>>,
+    /// A `TxEmpty` or `TxByte` of the current read has not been answered yet.
+    tx_requested: AtomicBool,
+    /// Bytes taken from the send buffer since the read started, over all refills.
+    tx_sent: AtomicUsize,
+
+    pub(crate) receive_buffer: Mutex<RefCell<R>>,
+    last_write: Mutex<RefCell<R>>,
+
+    pub(crate) state_holder: StateHolder<STATES_HISTORY_SIZE>,
+    pub(crate) state_hook: Option<fn(State, State)>,
+    pub(crate) backpressure: bool,
+
+    pub(crate) last_tx_byte: AtomicU8,
+    last_pec: AtomicU8,
+    last_error: Mutex<Cell<Option<Error>>>,
+    matched_address: Atomic<MatchedAddress>,
+    #[cfg(feature = "smbus")]
+    alert_response: AtomicBool,
+
+    rx_pending: AtomicBool,
+    addr_held: AtomicBool,
+    transferred: AtomicBool,
+    read_refused: AtomicBool,
+}
+
+impl<S: TxSource, R: RxSink, L: Lock> BridgeCore<S, R, L> {
+    pub(crate) const fn new(tx_lock: L) -> Self {
+        Self {
+            tx_lock,
+            send_buffer: Mutex::new(RefCell::new(S::INIT)),
+            staged_buffer: Mutex::new(RefCell::new(S::INIT)),
+            #[cfg(feature = "smbus")]
+            alert_stash: Mutex::new(RefCell::new(S::INIT)),
+            tx_requested: AtomicBool::new(false),
+            tx_sent: AtomicUsize::new(0),
+            receive_buffer: Mutex::new(RefCell::new(R::INIT)),
+            last_write: Mutex::new(RefCell::new(R::INIT)),
+            state_holder: StateHolder::new(),
+            state_hook: None,
+            backpressure: false,
+            last_tx_byte: AtomicU8::new(0xFF),
+            last_pec: AtomicU8::new(0),
+            last_error: Mutex::new(Cell::new(None)),
+            matched_address: Atomic::new(MatchedAddress::Primary),
+            #[cfg(feature = "smbus")]
+            alert_response: AtomicBool::new(false),
+            rx_pending: AtomicBool::new(false),
+            addr_held: AtomicBool::new(false),
+            transferred: AtomicBool::new(false),
+            read_refused: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn state(&self) -> State {
+        self.state_holder.get_state()
+    }
+
+    pub(crate) fn set_state(&self, state: State) {
+        let old = self.state_holder.get_state();
+        trace!("state {:?} -> {:?}", old, state);
+        debug_assert!(
+            is_legal_transition(old, state),
+            "illegal state transition {:?} -> {:?}",
+            old,
+            state
+        );
+        self.state_holder.set_state(state);
+
+        if matches!(state, State::TxInitial) {
+            self.tx_sent.store(0, Ordering::SeqCst);
+        }
+
+        if let Some(hook) = self.state_hook {
+            hook(old, state);
+        }
+    }
+
+    fn is_reading(&self) -> bool {
+        matches!(self.state(), State::TxInitial | State::TxRepeated)
+    }
+
+    pub(crate) fn lock_tx(&self, lock_type: TxLockType) {
+        self.tx_lock.lock(lock_type)
+    }
+
+    pub(crate) fn unlock_tx(&self) {
+        self.tx_lock.unlock()
+    }
+
+    pub(crate) fn write<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        let mut sb = self.send_buffer.borrow_ref_mut(cs);
+
+        // Outside of a read TX is not locked, unlocking would release a held address match.
+        if !self.is_reading() {
+            return sb.write(buf);
+        }
+
+        // The read has run out of data without asking for more, the master may be ending it.
+        if sb.is_empty() && !self.tx_requested.swap(false, Ordering::SeqCst) {
+            return self.staged_buffer.borrow_ref_mut(cs).write(buf);
+        }
+
+        let res = sb.write(buf);
+        self.tx_requested.store(false, Ordering::SeqCst);
+        self.unlock_tx();
+        res
+    }
+
+    pub(crate) fn clear_tx(&self, cs: CriticalSection) {
+        if !self.is_reading() {
+            self.send_buffer.borrow_ref_mut(cs).reset();
+        }
+    }
+
+    pub(crate) fn stage_tx<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        let res = self.staged_buffer.borrow_ref_mut(cs).write(buf);
+
+        // A read waiting for data at its start takes the staged buffer right away.
+        if matches!(self.state(), State::TxInitial) {
+            self.unlock_tx();
+        }
+
+        res
+    }
+
+    pub(crate) fn tx_remaining(&self, cs: CriticalSection) -> usize {
+        self.send_buffer.borrow_ref(cs).remaining()
+    }
+
+    pub(crate) fn read(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        let mut rb = self.receive_buffer.borrow_ref_mut(cs);
+        let r = rb.read(buf);
+
+        if r.is_ok() {
+            rb.reset();
+        }
+
+        r
+    }
+
+    pub(crate) fn read_some(&self, cs: CriticalSection, buf: &mut [u8]) -> usize {
+        self.receive_buffer.borrow_ref_mut(cs).read_some(buf)
+    }
+
+    pub(crate) fn read_with_crc(
+        &self,
+        cs: CriticalSection,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, u8)>, usize> {
+        let mut rb = self.receive_buffer.borrow_ref_mut(cs);
+        let r = rb.read_with_crc(buf);
+
+        if r.is_ok() {
+            rb.reset();
+        }
+
+        r
+    }
+
+    pub(crate) fn last_write(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        self.last_write.borrow_ref(cs).read(buf)
+    }
+
+    pub(crate) fn last_pec(&self) -> u8 {
+        self.last_pec.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_pec(&self, pec: u8) {
+        self.last_pec.store(pec, Ordering::SeqCst);
+    }
+
+    pub(crate) fn last_error(&self) -> Option<Error> {
+        critical_section::with(|cs| self.last_error.borrow(cs).get())
+    }
+
+    pub(crate) fn set_error(&self, error: Error) {
+        critical_section::with(|cs| self.last_error.borrow(cs).set(Some(error)));
+    }
+
+    pub(crate) fn matched_address(&self) -> MatchedAddress {
+        self.matched_address.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_matched_address(&self, address: MatchedAddress) {
+        self.matched_address.store(address, Ordering::SeqCst);
+    }
+
+    pub(crate) fn ack_processed(&self) {
+        self.rx_pending.store(false, Ordering::SeqCst);
+
+        if self.addr_held.swap(false, Ordering::SeqCst) {
+            self.unlock_tx();
+        }
+    }
+
+    pub(crate) fn hold_addr(&self) -> bool {
+        let hold = self.rx_pending.load(Ordering::SeqCst);
+        if hold {
+            self.addr_held.store(true, Ordering::SeqCst);
+        }
+        hold
+    }
+
+    pub(crate) fn reset(&self, cs: CriticalSection) {
+        self.last_error.borrow(cs).set(None);
+        self.send_buffer.borrow_ref_mut(cs).reset();
+        self.staged_buffer.borrow_ref_mut(cs).reset();
+        #[cfg(feature = "smbus")]
+        self.alert_stash.borrow_ref_mut(cs).reset();
+        self.tx_requested.store(false, Ordering::SeqCst);
+        self.receive_buffer.borrow_ref_mut(cs).reset();
+        // A write held back before the reset will not be processed any more.
+        self.rx_pending.store(false, Ordering::SeqCst);
+        self.addr_held.store(false, Ordering::SeqCst);
+        self.set_state(State::Idle);
+        self.unlock_tx();
+    }
+
+    /// Keeps the buffers in step with `event`, `emit` tells whether it reaches the consumer.
+    pub(crate) fn notify(&self, cs: CriticalSection, event: Event, emit: bool) {
+        match event {
+            Event::Control(Control::TxEmpty { .. } | Control::TxByte) => {
+                self.tx_requested.store(true, Ordering::SeqCst);
+            }
+            Event::Control(Control::Received { .. }) => {
+                if emit && self.backpressure {
+                    self.rx_pending.store(true, Ordering::SeqCst);
+                }
+
+                let mut rb = self.receive_buffer.borrow_ref_mut(cs);
+                *self.last_write.borrow_ref_mut(cs) = rb.clone();
+
+                // Nobody is told about the data, it would end up in front of the next write.
+                if !emit {
+                    rb.reset();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub(crate) fn get_rxbuf_size(&self, cs: CriticalSection) -> usize {
+        self.receive_buffer.borrow_ref(cs).get_size()
+    }
+
+    pub(crate) fn write_rxbuf_byte(&self, cs: CriticalSection, byte: u8) -> Result<(), ()> {
+        self.receive_buffer.borrow_ref_mut(cs).write_byte(byte)
+    }
+
+    pub(crate) fn take_rxbuf_high_water(&self, cs: CriticalSection) -> bool {
+        self.receive_buffer.borrow_ref_mut(cs).take_high_water()
+    }
+
+    pub(crate) fn reset_rxbuf(&self, cs: CriticalSection) {
+        self.receive_buffer.borrow_ref_mut(cs).reset();
+    }
+
+    pub(crate) fn pop_txbuf_byte(&self, cs: CriticalSection) -> Option<u8> {
+        let byte = self.send_buffer.borrow_ref_mut(cs).next_byte();
+
+        if let Some(byte) = byte {
+            self.last_tx_byte.store(byte, Ordering::SeqCst);
+            self.tx_sent.fetch_add(1, Ordering::SeqCst);
+        }
+
+        byte
+    }
+
+    /// Marks the first `n` pending bytes as sent, they went out with a DMA transfer.
+    #[cfg(feature = "dma")]
+    pub(crate) fn advance_txbuf(&self, cs: CriticalSection, n: usize) {
+        let mut sb = self.send_buffer.borrow_ref_mut(cs);
+
+        if let Some(&byte) = sb.pending()[..n].last() {
+            self.last_tx_byte.store(byte, Ordering::SeqCst);
+        }
+
+        sb.advance(n);
+        self.tx_sent.fetch_add(n, Ordering::SeqCst);
+    }
+
+    pub(crate) fn promote_txbuf(&self, cs: CriticalSection) {
+        self.last_tx_byte.store(0xFF, Ordering::SeqCst);
+
+        // The staged buffer is kept for the read after the alert response.
+        #[cfg(feature = "smbus")]
+        if self.alert_response.swap(false, Ordering::SeqCst) {
+            return;
+        }
+
+        let mut staged = self.staged_buffer.borrow_ref_mut(cs);
+
+        if !staged.is_empty() {
+            let mut sb = self.send_buffer.borrow_ref_mut(cs);
+            core::mem::swap(&mut *sb, &mut *staged);
+            staged.reset();
+        }
+    }
+
+    pub(crate) fn is_txbuf_empty(&self, cs: CriticalSection) -> bool {
+        self.send_buffer.borrow_ref(cs).is_empty()
+    }
+
+    /// Drops what is left of the read, returns the number of bytes sent since it started.
+    ///
+    /// The send buffer starts over whenever it is refilled, so it cannot tell on its own.
+    pub(crate) fn reset_txbuf(&self, cs: CriticalSection) -> usize {
+        let mut sb = self.send_buffer.borrow_ref_mut(cs);
+        sb.reset();
+        self.tx_requested.store(false, Ordering::SeqCst);
+
+        // The alert response is over, the response written before it is back for the next read.
+        #[cfg(feature = "smbus")]
+        {
+            let mut stash = self.alert_stash.borrow_ref_mut(cs);
+            if !stash.is_empty() {
+                core::mem::swap(&mut *sb, &mut *stash);
+                stash.reset();
+            }
+        }
+
+        self.tx_sent.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_transferred(&self, transferred: bool) {
+        self.transferred.store(transferred, Ordering::SeqCst);
+    }
+
+    pub(crate) fn transferred(&self) -> bool {
+        self.transferred.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn set_read_refused(&self, refused: bool) {
+        self.read_refused.store(refused, Ordering::SeqCst);
+    }
+
+    pub(crate) fn read_refused(&self) -> bool {
+        self.read_refused.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "smbus")]
+    pub(crate) fn respond_alert(&self, cs: CriticalSection, address: u8) {
+        let mut sb = self.send_buffer.borrow_ref_mut(cs);
+        core::mem::swap(&mut *sb, &mut *self.alert_stash.borrow_ref_mut(cs));
+        sb.reset();
+        sb.write(&[address]);
+        self.alert_response.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,72 @@
+use embassy_stm32::gpio::Pull;
+
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Analog noise filter on SCL/SDA.
+    ///
+    /// The F1 peripheral has an always-on analog filter and no filter register, so only
+    /// `true` is accepted there.
+    pub analog_filter: bool,
+    /// Digital noise filter length in PCLK cycles, `0` disables it.
+    ///
+    /// Not available on the F1 peripheral, only `0` is accepted there.
+    pub digital_filter: u8,
+    /// Let the hardware compute the PEC of each transaction, see [`I2CSlave::last_pec`].
+    ///
+    /// [`I2CSlave::last_pec`]: crate::I2CSlave::last_pec
+    pub pec: bool,
+    /// Pull resistors on SCL and SDA.
+    ///
+    /// `Pull::Up` enables the internal pull-ups for boards without external ones. They are
+    /// weak, roughly 40 kΩ, which is only enough for short buses at low speeds.
+    pub pull: Pull,
+    /// Disable the peripheral when the interrupt handlers fail.
+    ///
+    /// With `false` only the I2C interrupts are disabled, the registers keep their state for
+    /// inspection through [`I2CSlave::regs`]. SCL may stay stretched until
+    /// [`I2CSlave::rearm`] is called.
+    ///
+    /// [`I2CSlave::regs`]: crate::I2CSlave::regs
+    /// [`I2CSlave::rearm`]: crate::I2CSlave::rearm
+    pub disable_on_error: bool,
+    /// Never stretch SCL.
+    ///
+    /// The responses then have to be in the send buffer before the master reads: a
+    /// `TxEmpty` underrun sends whatever is in DR instead of stalling the bus and is reported
+    /// as `Underrun`, which can be made recoverable with
+    /// [`Bridge::with_recoverable`](crate::Bridge::with_recoverable). Writes coming in faster
+    /// than the handler stores them are reported as `Overrun`.
+    pub no_stretch: bool,
+    /// Take the own address as a 10-bit address.
+    ///
+    /// The general call and [`Config::own_address2`] are still 7-bit and answered as before.
+    /// A read from a 10-bit address starts as a write of the address alone, turned around
+    /// with a repeated start. That part shows up as `Addr { tx: false, .. }` only, without a
+    /// `Received`.
+    pub ten_bit_address: bool,
+    /// Second 7-bit address to answer to, enables the dual-address mode.
+    ///
+    /// The events tell the addresses apart with [`MatchedAddress`](crate::MatchedAddress).
+    /// The F1 peripheral has no address mask, two addresses and the general call are all it
+    /// can answer to.
+    pub own_address2: Option<u8>,
+    /// Maximum SCL rise time programmed into TRISE, `None` uses the Standard-mode 1000 ns.
+    pub rise_time_ns: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            analog_filter: true,
+            digital_filter: 0,
+            pec: false,
+            pull: Pull::None,
+            disable_on_error: true,
+            no_stretch: false,
+            ten_bit_address: false,
+            own_address2: None,
+            rise_time_ns: None,
+        }
+    }
+}
@@ -1,13 +1,29 @@
+use core::marker::PhantomData;
+
+#[cfg(feature = "profiling")]
+use cortex_m::peripheral::DWT;
 use critical_section::CriticalSection;
 use embassy_stm32::i2c;
+#[cfg(feature = "dma")]
+use embassy_stm32::pac::i2c::regs::Cr2;
+use embassy_stm32::pac::i2c::regs::{Cr1, Oar1, Sr1, Sr2};
+use embassy_stm32::pac::i2c::vals::Addmode;
+#[cfg(feature = "dma")]
+use embassy_stm32::pac::{self, bdma::vals::Dir, DMA1};
 
 use super::{
-    tx_lock::TxLockType, Control, Event, I2CError, Notification, ProtocolError, Reason, State,
+    tx_lock::TxLockType, Control, Event, I2CError, MatchedAddress, Mode, Notification,
+    ProtocolError, Reason, State,
 };
 
 pub trait InterruptBridge<T: i2c::Instance> {
     fn get_state(&self) -> State;
 
+    fn mode(&self) -> Mode;
+
+    /// Byte to send when the send buffer is empty, `None` to stretch SCL instead.
+    fn underrun_byte(&self) -> Option<u8>;
+
     fn set_state(&self, state: State);
 
     fn fail(&self, err: Reason);
@@ -22,23 +38,604 @@ pub trait InterruptBridge<T: i2c::Instance> {
 
     fn write_rxbuf_byte(&self, cs: CriticalSection, byte: u8) -> Result<(), ()>;
 
+    fn take_rxbuf_high_water(&self, cs: CriticalSection) -> bool;
+
+    /// Marks the end of a write, for [`Bridge::with_frames`](crate::Bridge::with_frames).
+    #[cfg(feature = "frames")]
+    fn end_frame(&self, cs: CriticalSection);
+
     fn pop_txbuf_byte(&self, cs: CriticalSection) -> Option<u8>;
 
+    fn promote_txbuf(&self, cs: CriticalSection);
+
+    fn fill_txbuf(&self, cs: CriticalSection, initial: bool) -> bool;
+
+    fn is_txbuf_empty(&self, cs: CriticalSection) -> bool;
+
+    #[cfg(feature = "dma")]
+    fn tx_dma_channel(&self) -> Option<usize>;
+
+    /// Returns the pending bytes for a DMA transfer and remembers how many were handed out.
+    #[cfg(feature = "dma")]
+    fn start_txbuf_dma(&self, cs: CriticalSection) -> Option<(*const u8, usize)>;
+
+    /// Marks the bytes of the last DMA transfer as sent, except the `remaining` ones.
+    #[cfg(feature = "dma")]
+    fn end_txbuf_dma(&self, cs: CriticalSection, remaining: usize);
+
     fn reset_txbuf(&self, cs: CriticalSection) -> usize;
+
+    fn reset_rxbuf(&self, cs: CriticalSection);
+
+    #[cfg(feature = "dma")]
+    fn rx_dma_channel(&self) -> Option<usize>;
+
+    /// Returns the free space of the receive buffer for a DMA transfer and remembers its size.
+    #[cfg(feature = "dma")]
+    fn start_rxbuf_dma(&self, cs: CriticalSection) -> Option<(*mut u8, usize)>;
+
+    /// Marks the bytes of the last DMA transfer as received, except the `remaining` ones.
+    #[cfg(feature = "dma")]
+    fn end_rxbuf_dma(&self, cs: CriticalSection, remaining: usize);
+
+    fn set_pec(&self, pec: u8);
+
+    fn get_matched_address(&self) -> MatchedAddress;
+
+    fn set_matched_address(&self, address: MatchedAddress);
+
+    fn hold_addr(&self) -> bool;
+
+    /// Records whether a data byte has been received, or clocked out in a read, since the
+    /// last address match.
+    fn set_transferred(&self, transferred: bool);
+
+    fn transferred(&self) -> bool;
+
+    /// Whether the own address is ACKed again at STOP, `false` while the slave is set not to
+    /// respond.
+    fn ack_address(&self) -> bool;
+
+    /// Asks the application whether to take part in the transaction just addressed.
+    fn accept_addr(&self, tx: bool, gencall: bool) -> bool;
+
+    /// Records whether the current read was refused by [`InterruptBridge::accept_addr`].
+    fn set_read_refused(&self, refused: bool);
+
+    fn read_refused(&self) -> bool;
+
+    fn stop_detected(&self);
+
+    #[cfg(feature = "smbus")]
+    fn respond_alert(&self, cs: CriticalSection, address: u8);
+
+    fn is_recoverable(&self, err: I2CError) -> bool;
+
+    #[cfg(feature = "profiling")]
+    fn record_isr_cycles(&self, cycles: u32);
+
+    #[cfg(feature = "stats")]
+    fn count_spurious_interrupt(&self);
+}
+
+/// The registers the interrupt handlers work with, so that they can also be driven by
+/// [`MockBridge::replay`](crate::MockBridge::replay).
+pub trait Registers {
+    fn sr1(&self) -> Sr1;
+
+    fn modify_sr1(&self, f: impl FnOnce(&mut Sr1));
+
+    fn sr2(&self) -> Sr2;
+
+    fn cr1(&self) -> Cr1;
+
+    fn modify_cr1(&self, f: impl FnOnce(&mut Cr1));
+
+    #[cfg(feature = "dma")]
+    fn cr2(&self) -> Cr2;
+
+    #[cfg(feature = "dma")]
+    fn modify_cr2(&self, f: impl FnOnce(&mut Cr2));
+
+    fn oar1(&self) -> Oar1;
+
+    fn read_dr(&self) -> u8;
+
+    fn write_dr(&self, byte: u8);
+
+    /// Address of DR, for the peripheral side of a DMA transfer.
+    #[cfg(feature = "dma")]
+    fn dr_address(&self) -> u32;
+}
+
+/// The registers of the peripheral `T`.
+pub struct PeripheralRegisters<T: i2c::Instance> {
+    _marker: PhantomData<T>,
+}
+
+impl<T: i2c::Instance> PeripheralRegisters<T> {
+    pub const fn new() -> Self {
+        Self {
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: i2c::Instance> Registers for PeripheralRegisters<T> {
+    fn sr1(&self) -> Sr1 {
+        T::regs().sr1().read()
+    }
+
+    fn modify_sr1(&self, f: impl FnOnce(&mut Sr1)) {
+        T::regs().sr1().modify(f)
+    }
+
+    fn sr2(&self) -> Sr2 {
+        T::regs().sr2().read()
+    }
+
+    fn cr1(&self) -> Cr1 {
+        T::regs().cr1().read()
+    }
+
+    fn modify_cr1(&self, f: impl FnOnce(&mut Cr1)) {
+        T::regs().cr1().modify(f)
+    }
+
+    #[cfg(feature = "dma")]
+    fn cr2(&self) -> Cr2 {
+        T::regs().cr2().read()
+    }
+
+    #[cfg(feature = "dma")]
+    fn modify_cr2(&self, f: impl FnOnce(&mut Cr2)) {
+        T::regs().cr2().modify(f)
+    }
+
+    fn oar1(&self) -> Oar1 {
+        T::regs().oar1().read()
+    }
+
+    fn read_dr(&self) -> u8 {
+        T::regs().dr().read().dr()
+    }
+
+    fn write_dr(&self, byte: u8) {
+        T::regs().dr().write(|w| w.set_dr(byte))
+    }
+
+    #[cfg(feature = "dma")]
+    fn dr_address(&self) -> u32 {
+        T::regs().dr().as_ptr() as u32
+    }
+}
+
+/// Index into DMA1 of the channel wired to the TX request of `T`, channel 6 for I2C1 and 4 for
+/// I2C2 in the numbering of the reference manual.
+#[cfg(feature = "dma")]
+pub(crate) fn tx_dma_channel<T: i2c::Instance>() -> usize {
+    if T::regs().as_ptr() == pac::I2C1.as_ptr() {
+        5
+    } else {
+        3
+    }
+}
+
+/// Index into DMA1 of the channel wired to the RX request of `T`, channel 7 for I2C1 and 5 for
+/// I2C2 in the numbering of the reference manual.
+#[cfg(feature = "dma")]
+pub(crate) fn rx_dma_channel<T: i2c::Instance>() -> usize {
+    if T::regs().as_ptr() == pac::I2C1.as_ptr() {
+        6
+    } else {
+        4
+    }
+}
+
+#[cfg(feature = "dma")]
+fn start_dma(regs: &impl Registers, n: usize, dir: Dir, mem: u32, len: usize) {
+    let ch = DMA1.ch(n);
+    ch.par().write_value(regs.dr_address());
+    ch.mar().write_value(mem);
+    ch.ndtr().write(|w| w.set_ndt(len as u16));
+    ch.cr().write(|w| {
+        w.set_dir(dir);
+        w.set_minc(true);
+        w.set_tcie(true);
+        w.set_en(true);
+    });
+
+    regs.modify_cr2(|w| w.set_dmaen(true));
+}
+
+/// Stops the transfer on channel `n` if one is running, returns the bytes it had left.
+#[cfg(feature = "dma")]
+fn stop_dma(regs: &impl Registers, n: usize) -> Option<usize> {
+    if !regs.cr2().dmaen() {
+        return None;
+    }
+
+    let ch = DMA1.ch(n);
+    ch.cr().modify(|w| w.set_en(false));
+    regs.modify_cr2(|w| w.set_dmaen(false));
+    DMA1.ifcr().write(|w| w.set_tcif(n, true));
+
+    Some(ch.ndtr().read().ndt() as usize)
+}
+
+#[cfg(feature = "dma")]
+fn start_tx_dma<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    cs: CriticalSection,
+) -> bool {
+    let Some(n) = bridge.tx_dma_channel() else {
+        return false;
+    };
+
+    match bridge.start_txbuf_dma(cs) {
+        Some((ptr, len)) => {
+            start_dma(regs, n, Dir::FROMMEMORY, ptr as u32, len);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops the running DMA transfer, returns `false` if there was none.
+#[cfg(feature = "dma")]
+fn stop_tx_dma<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    cs: CriticalSection,
+) -> bool {
+    let Some(remaining) = bridge.tx_dma_channel().and_then(|n| stop_dma(regs, n)) else {
+        return false;
+    };
+
+    bridge.end_txbuf_dma(cs, remaining);
+    true
+}
+
+#[cfg(feature = "dma")]
+fn start_rx_dma<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    cs: CriticalSection,
+) -> bool {
+    let Some(n) = bridge.rx_dma_channel() else {
+        return false;
+    };
+
+    match bridge.start_rxbuf_dma(cs) {
+        Some((ptr, len)) => {
+            start_dma(regs, n, Dir::FROMPERIPHERAL, ptr as u32, len);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stops the running DMA transfer and gives RXNE back to the event interrupt, returns `false`
+/// if there was no transfer.
+#[cfg(feature = "dma")]
+fn stop_rx_dma<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    cs: CriticalSection,
+) -> bool {
+    let Some(remaining) = bridge.rx_dma_channel().and_then(|n| stop_dma(regs, n)) else {
+        return false;
+    };
+
+    bridge.end_rxbuf_dma(cs, remaining);
+    bridge.unlock_tx();
+    true
+}
+
+#[cfg_attr(not(feature = "dma"), allow(unused_variables))]
+fn recover<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    error: I2CError,
+) {
+    warn!("recovering from {:?} in {:?}", error, bridge.get_state());
+
+    critical_section::with(|cs| {
+        #[cfg(feature = "dma")]
+        match bridge.get_state() {
+            State::TxInitial | State::TxRepeated => stop_tx_dma(regs, bridge, cs),
+            State::Rx => stop_rx_dma(regs, bridge, cs),
+            State::Idle | State::Nack => false,
+        };
+
+        bridge.reset_txbuf(cs);
+        bridge.reset_rxbuf(cs);
+    });
+    bridge.unlock_tx();
+    bridge.set_state(State::Idle);
+    bridge.notify(Event::Notification(Notification::Recovered { error }));
+}
+
+/// Resets the send buffer of a read that ended without a NACK and reports what went out.
+#[cfg_attr(not(feature = "dma"), allow(unused_variables))]
+fn end_read<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(regs: &R, bridge: &B) {
+    let sent = critical_section::with(|cs| {
+        #[cfg(feature = "dma")]
+        stop_tx_dma(regs, bridge, cs);
+
+        bridge.reset_txbuf(cs)
+    });
+
+    bridge.notify(Event::Notification(Notification::Sent { sent }));
+}
+
+fn handle_nack<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+) -> bool {
+    regs.modify_sr1(|w| w.set_af(false));
+
+    match bridge.get_state() {
+        State::TxInitial | State::TxRepeated => {
+            bridge.set_state(State::Nack);
+
+            // Only the fill byte went out, the send buffer is left for the next read.
+            if bridge.read_refused() {
+                return true;
+            }
+
+            let (sent, early) = critical_section::with(|cs| {
+                #[cfg(feature = "dma")]
+                stop_tx_dma(regs, bridge, cs);
+
+                let early = !bridge.is_txbuf_empty(cs);
+                (bridge.reset_txbuf(cs), early)
+            });
+
+            bridge.notify(Event::Notification(Notification::Sent { sent }));
+            if early {
+                bridge.notify(Event::Notification(Notification::Nacked));
+            }
+            true
+        }
+        State::Idle | State::Rx | State::Nack => {
+            bridge.fail(Reason::Protocol(ProtocolError::NackWhileNotTranseiving));
+            false
+        }
+    }
+}
+
+fn handle_stop<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+    af_pending: bool,
+) -> bool {
+    if regs.cr1().enpec() {
+        bridge.set_pec(regs.sr2().pec());
+    }
+
+    // Clearing STOPF takes a write to CR1, which also undoes a NACK set with `Bridge::set_ack`
+    // for the next transaction, whose address byte would be NACKed otherwise.
+    regs.modify_cr1(|w| {
+        w.set_pe(true);
+        w.set_ack(bridge.ack_address());
+    });
+
+    debug!("STOP in {:?}", bridge.get_state());
+    bridge.stop_detected();
+
+    // The NACK ending a read may still be pending in the error interrupt.
+    if af_pending
+        && matches!(bridge.get_state(), State::TxInitial | State::TxRepeated)
+        && !handle_nack(regs, bridge)
+    {
+        return false;
+    }
+
+    match bridge.get_state() {
+        state @ (State::Idle | State::Rx | State::Nack) => {
+            if matches!(state, State::Rx) {
+                #[cfg(feature = "dma")]
+                critical_section::with(|cs| stop_rx_dma(regs, bridge, cs));
+
+                bridge.notify(Event::Control(Control::Received {
+                    size: critical_section::with(|cs| bridge.get_rxbuf_size(cs)),
+                    write: false,
+                    address: bridge.get_matched_address(),
+                }));
+
+                #[cfg(feature = "frames")]
+                critical_section::with(|cs| bridge.end_frame(cs));
+
+                if !bridge.transferred() {
+                    bridge.notify(Event::Notification(Notification::QuickCommand {
+                        write: true,
+                    }));
+                }
+            }
+
+            if !matches!(state, State::Idle) {
+                bridge.set_state(State::Idle);
+                bridge.notify(Event::Notification(Notification::Stop));
+            }
+        }
+        // A read stopped before a byte was clocked out.
+        State::TxInitial | State::TxRepeated if !bridge.transferred() => {
+            end_read(regs, bridge);
+            bridge.notify(Event::Notification(Notification::QuickCommand {
+                write: false,
+            }));
+            bridge.set_state(State::Idle);
+            bridge.notify(Event::Notification(Notification::Stop));
+        }
+        State::TxInitial | State::TxRepeated => {
+            end_read(regs, bridge);
+            bridge.fail(Reason::Protocol(ProtocolError::StopDuringTransmission));
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Answers the Alert Response Address with the own address and releases SMBA.
+///
+/// The peripheral does not flag which address matched, so while the alert is asserted any
+/// read is taken as the host reading the ARA.
+#[cfg(feature = "smbus")]
+fn respond_alert<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(regs: &R, bridge: &B) {
+    let oar1 = regs.oar1();
+    let address = if oar1.addmode() == Addmode::BIT10 {
+        // The header byte of a write, carrying the two high bits of the 10-bit address.
+        0xF0 | ((oar1.add() >> 7) as u8 & 0x06)
+    } else {
+        // In 7-bit mode ADD holds the address shifted left by one, as sent on the bus.
+        (oar1.add() & 0xFE) as u8
+    };
+
+    regs.modify_cr1(|w| w.set_alert(false));
+    critical_section::with(|cs| bridge.respond_alert(cs, address));
+    bridge.notify(Event::Notification(Notification::AlertResponse));
 }
 
 pub fn handle_event_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &B) {
-    let regs = T::regs();
-    let sr1 = regs.sr1().read();
+    #[cfg(feature = "profiling")]
+    let start = DWT::cycle_count();
+
+    event_interrupt(&PeripheralRegisters::<T>::new(), bridge);
+
+    #[cfg(feature = "profiling")]
+    bridge.record_isr_cycles(DWT::cycle_count().wrapping_sub(start));
+}
+
+pub fn handle_error_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &B) {
+    #[cfg(feature = "profiling")]
+    let start = DWT::cycle_count();
+
+    error_interrupt(&PeripheralRegisters::<T>::new(), bridge);
+
+    #[cfg(feature = "profiling")]
+    bridge.record_isr_cycles(DWT::cycle_count().wrapping_sub(start));
+}
+
+/// Handles the transfer complete interrupt of the channels set with
+/// [`I2CSlave::with_tx_dma`](crate::I2CSlave::with_tx_dma) and
+/// [`I2CSlave::with_rx_dma`](crate::I2CSlave::with_rx_dma).
+#[cfg(feature = "dma")]
+pub fn handle_dma_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &B) {
+    let regs = &PeripheralRegisters::<T>::new();
+    let isr = DMA1.isr().read();
+
+    if let Some(n) = bridge.tx_dma_channel().filter(|&n| isr.tcif(n)) {
+        DMA1.ifcr().write(|w| w.set_tcif(n, true));
+
+        // Hands TXE back to the event interrupt, unless a NACK has already ended the transfer.
+        if critical_section::with(|cs| stop_tx_dma(regs, bridge, cs)) {
+            bridge.unlock_tx();
+        }
+    }
+
+    if let Some(n) = bridge.rx_dma_channel().filter(|&n| isr.tcif(n)) {
+        DMA1.ifcr().write(|w| w.set_tcif(n, true));
+
+        // The receive buffer is full, the rest of the write goes through the event interrupt.
+        if let Some(size) = critical_section::with(|cs| {
+            stop_rx_dma(regs, bridge, cs).then(|| bridge.get_rxbuf_size(cs))
+        }) {
+            bridge.notify(Event::Control(Control::Received {
+                size,
+                write: false,
+                address: bridge.get_matched_address(),
+            }));
+        }
+    }
+}
+
+pub(crate) fn event_interrupt<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+) {
+    let mut sr1 = regs.sr1();
+
+    #[cfg(feature = "stats")]
+    if !(sr1.rxne() || sr1.addr() || sr1.txe() || sr1.stopf()) {
+        bridge.count_spurious_interrupt();
+    }
 
     if sr1.txe() && sr1.rxne() {
-        return bridge.fail(Reason::Protocol(ProtocolError::RxneAndTxne));
+        match bridge.get_state() {
+            // The last byte of a write followed by a fast repeated start can still be in DR
+            // once the read has begun. It is too late for the write, so it is dropped.
+            State::TxInitial | State::TxRepeated => {
+                let _ = regs.read_dr();
+                sr1.set_rxne(false);
+            }
+            state => return bridge.fail(Reason::Protocol(ProtocolError::RxneAndTxne { state })),
+        }
+    }
+
+    // On a repeated start the last byte of the write may still be in DR while ADDR is already
+    // set, so it has to be stored before the address match switches the state.
+    if sr1.rxne() {
+        match bridge.get_state() {
+            State::Idle | State::TxInitial | State::TxRepeated | State::Nack => {
+                return bridge.fail(Reason::Protocol(ProtocolError::RxneWhileNotReceiving))
+            }
+            // The byte is the DMA's to take.
+            #[cfg(feature = "dma")]
+            State::Rx if regs.cr2().dmaen() => {}
+            State::Rx if matches!(bridge.mode(), Mode::RawByte) => {
+                let byte = regs.read_dr();
+                bridge.set_transferred(true);
+                bridge.notify(Event::Control(Control::RxByte(byte)));
+            }
+            State::Rx => {
+                let byte = regs.read_dr();
+                bridge.set_transferred(true);
+                let res = critical_section::with(|cs| {
+                    bridge.write_rxbuf_byte(cs, byte)?;
+                    Ok(bridge
+                        .take_rxbuf_high_water(cs)
+                        .then(|| bridge.get_rxbuf_size(cs)))
+                });
+
+                match res {
+                    Err(()) => return bridge.fail(Reason::ReceiveBufferFull),
+                    Ok(Some(size)) => bridge.notify(Event::Control(Control::Received {
+                        size,
+                        write: false,
+                        address: bridge.get_matched_address(),
+                    })),
+                    Ok(None) => {}
+                }
+            }
+        }
+    }
+
+    // With back-to-back transactions the STOP of the previous one can be pending together
+    // with the address match of the next one. It has to be handled first, or it would end
+    // the new transaction. SCL is stretched from the address match on, so a STOP after it
+    // can only be pending as well without clock stretching, as in a quick command.
+    let stop_first = sr1.stopf()
+        && sr1.addr()
+        && (!matches!(bridge.get_state(), State::Idle) || !regs.cr1().nostretch());
+    if stop_first && !handle_stop(regs, bridge, sr1.af()) {
+        return;
     }
 
     if sr1.addr() {
+        if matches!(bridge.get_state(), State::Idle) && bridge.hold_addr() {
+            // Leaving ADDR set stretches SCL until the consumer has processed the last write.
+            return bridge.lock_tx(TxLockType::Addr);
+        }
+
         match bridge.get_state() {
+            // Coming from `Nack`, `handle_nack` has already stopped the DMA, reset the send
+            // buffer and emitted `Sent`. Anything in the send buffer now was written by the
+            // consumer since, for this transaction, so it is not reset again.
             state @ (State::Idle | State::Rx | State::Nack) => {
-                let sr2 = regs.sr2().read();
+                let sr2 = regs.sr2();
 
                 let transmission = sr2.tra();
                 let general_call = sr2.gencall();
@@ -48,38 +645,79 @@ pub fn handle_event_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
                 } else {
                     State::Rx
                 });
+                bridge.set_transferred(false);
 
                 if matches!(state, State::Rx) {
-                    bridge.notify(Event::Control(Control::Received {
-                        size: critical_section::with(|cs| bridge.get_rxbuf_size(cs)),
-                        write: transmission,
-                    }));
+                    #[cfg(feature = "dma")]
+                    critical_section::with(|cs| stop_rx_dma(regs, bridge, cs));
+
+                    let size = critical_section::with(|cs| bridge.get_rxbuf_size(cs));
+
+                    // A read from a 10-bit address is a write of the address alone turned
+                    // around with a repeated start, there was no write to report. The general
+                    // call has a 7-bit address, it always is a write.
+                    let header = transmission
+                        && size == 0
+                        && regs.oar1().addmode() == Addmode::BIT10
+                        && !matches!(bridge.get_matched_address(), MatchedAddress::GeneralCall);
+
+                    if !header {
+                        bridge.notify(Event::Control(Control::Received {
+                            size,
+                            write: transmission,
+                            address: bridge.get_matched_address(),
+                        }));
+                    }
+
+                    #[cfg(feature = "frames")]
+                    critical_section::with(|cs| bridge.end_frame(cs));
+                }
+
+                debug!(
+                    "address match in {:?}: tx {}, gencall {}",
+                    state, transmission, general_call
+                );
+
+                let refused = !bridge.accept_addr(transmission, general_call);
+
+                // A write that is not accepted, or was addressed just before the slave was set
+                // not to respond, gets its data bytes NACKed. The address itself is ACKed by
+                // now.
+                if !transmission && (refused || !bridge.ack_address()) {
+                    regs.modify_cr1(|w| w.set_ack(false));
                 }
 
+                // A read cannot be NACKed, it is answered with the fill byte instead.
+                bridge.set_read_refused(transmission && refused);
+
+                bridge.set_matched_address(if general_call {
+                    MatchedAddress::GeneralCall
+                } else if sr2.dualf() {
+                    MatchedAddress::Secondary
+                } else {
+                    MatchedAddress::Primary
+                });
+
                 bridge.notify(Event::Notification(Notification::Addr {
                     tx: transmission,
                     gencall: general_call,
                 }));
-            }
-            State::TxInitial | State::TxRepeated => {
-                return bridge.fail(Reason::Protocol(ProtocolError::AddrDuringTransmission))
-            }
-        }
-    }
 
-    if sr1.rxne() {
-        match bridge.get_state() {
-            State::Idle | State::TxInitial | State::TxRepeated | State::Nack => {
-                return bridge.fail(Reason::Protocol(ProtocolError::RxneWhileNotReceiving))
-            }
-            State::Rx => {
-                let byte = T::regs().dr().read().dr();
-                let res = critical_section::with(|cs| bridge.write_rxbuf_byte(cs, byte));
+                #[cfg(feature = "smbus")]
+                if transmission && !refused && !general_call && regs.cr1().alert() {
+                    respond_alert(regs, bridge);
+                }
 
-                if res.is_err() {
-                    return bridge.fail(Reason::ReceiveBufferFull);
+                // RXNE is left to the DMA until the write ends or the buffer fills up.
+                #[cfg(feature = "dma")]
+                if !transmission && critical_section::with(|cs| start_rx_dma(regs, bridge, cs)) {
+                    bridge.lock_tx(TxLockType::TxOnly);
                 }
             }
+            State::TxInitial | State::TxRepeated => {
+                end_read(regs, bridge);
+                return bridge.fail(Reason::Protocol(ProtocolError::AddrDuringTransmission));
+            }
         }
     }
 
@@ -88,21 +726,77 @@ pub fn handle_event_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
             State::Idle | State::Rx | State::Nack => {
                 return bridge.fail(Reason::Protocol(ProtocolError::TxeWhileNotTranseiving))
             }
+            state @ (State::TxInitial | State::TxRepeated) if bridge.read_refused() => {
+                regs.write_dr(bridge.underrun_byte().unwrap_or(0xFF));
+
+                if matches!(state, State::TxInitial) {
+                    bridge.set_state(State::TxRepeated);
+                }
+            }
             state @ (State::TxInitial | State::TxRepeated) => {
                 let initial = matches!(state, State::TxInitial);
 
-                if initial || sr1.btf() {
-                    let optbyte = critical_section::with(|cs| bridge.pop_txbuf_byte(cs));
+                if initial {
+                    critical_section::with(|cs| bridge.promote_txbuf(cs));
+                }
+
+                // BTF means the byte before has been clocked out.
+                if !initial && sr1.btf() {
+                    bridge.set_transferred(true);
+                }
+
+                // BTF with DMA enabled means the transfer has run dry, it may land here before
+                // the transfer complete interrupt.
+                #[cfg(feature = "dma")]
+                if !initial && sr1.btf() {
+                    critical_section::with(|cs| stop_tx_dma(regs, bridge, cs));
+                }
+
+                #[cfg(feature = "dma")]
+                let dma = initial && critical_section::with(|cs| start_tx_dma(regs, bridge, cs));
+                #[cfg(not(feature = "dma"))]
+                let dma = false;
+
+                if dma {
+                    // DR is fed by the DMA until the transfer complete interrupt.
+                    bridge.set_state(State::TxRepeated);
+                    bridge.lock_tx(TxLockType::TxOnly);
+                } else if initial || sr1.btf() {
+                    let optbyte = critical_section::with(|cs| match bridge.pop_txbuf_byte(cs) {
+                        None if bridge.fill_txbuf(cs, initial) => bridge.pop_txbuf_byte(cs),
+                        optbyte => optbyte,
+                    });
 
                     if let Some(byte) = optbyte {
-                        T::regs().dr().write(|w| w.set_dr(byte));
+                        regs.write_dr(byte);
 
                         if initial {
                             bridge.set_state(State::TxRepeated);
                         }
                     } else {
-                        bridge.lock_tx(TxLockType::TxAndBtf);
-                        bridge.notify(Event::Control(Control::TxEmpty { initial }));
+                        let event = Event::Control(match bridge.mode() {
+                            Mode::Buffered => Control::TxEmpty {
+                                initial,
+                                address: bridge.get_matched_address(),
+                            },
+                            Mode::RawByte => Control::TxByte,
+                        });
+
+                        match bridge.underrun_byte() {
+                            // Padding goes out unannounced, except at the start of the read.
+                            Some(byte) => {
+                                regs.write_dr(byte);
+
+                                if initial {
+                                    bridge.set_state(State::TxRepeated);
+                                    bridge.notify(event);
+                                }
+                            }
+                            None => {
+                                bridge.lock_tx(TxLockType::TxAndBtf);
+                                bridge.notify(event);
+                            }
+                        }
                     }
                 } else {
                     // Waitinf for BTF.
@@ -112,46 +806,53 @@ pub fn handle_event_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
         }
     }
 
-    if sr1.stopf() {
-        regs.cr1().modify(|w| w.set_pe(true));
-
-        match bridge.get_state() {
-            state @ (State::Idle | State::Rx | State::Nack) => {
-                if matches!(state, State::Rx) {
-                    bridge.notify(Event::Control(Control::Received {
-                        size: critical_section::with(|cs| bridge.get_rxbuf_size(cs)),
-                        write: false,
-                    }));
-                }
-
-                if !matches!(state, State::Idle) {
-                    bridge.set_state(State::Idle);
-                    bridge.notify(Event::Notification(Notification::Stop));
-                }
-            }
-            State::TxInitial | State::TxRepeated => {
-                return bridge.fail(Reason::Protocol(ProtocolError::StopDuringTransmission))
-            }
-        }
+    if sr1.stopf() && !stop_first {
+        handle_stop(regs, bridge, sr1.af());
     }
 }
 
-pub fn handle_error_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &B) {
-    let regs = T::regs();
-    let sr1 = regs.sr1().read();
+pub(crate) fn error_interrupt<T: i2c::Instance, R: Registers, B: InterruptBridge<T>>(
+    regs: &R,
+    bridge: &B,
+) {
+    let sr1 = regs.sr1();
+
+    #[cfg(feature = "stats")]
+    if !(sr1.af()
+        || sr1.ovr()
+        || sr1.berr()
+        || sr1.arlo()
+        || sr1.pecerr()
+        || sr1.timeout()
+        || sr1.alert())
+    {
+        bridge.count_spurious_interrupt();
+    }
+
+    if sr1.af() && !handle_nack(regs, bridge) {
+        return;
+    }
 
-    if sr1.af() {
-        regs.sr1().modify(|w| w.set_af(false));
+    // OVR is set for both directions, the state tells which one it was.
+    if sr1.ovr() {
+        regs.modify_sr1(|w| w.set_ovr(false));
 
         match bridge.get_state() {
+            State::TxInitial | State::TxRepeated if bridge.is_recoverable(I2CError::Underrun) => {
+                // The stale byte has already gone out, the read goes on with the data written
+                // after the notification.
+                critical_section::with(|cs| bridge.reset_txbuf(cs));
+                bridge.notify(Event::Notification(Notification::Recovered {
+                    error: I2CError::Underrun,
+                }));
+            }
             State::TxInitial | State::TxRepeated => {
-                bridge.set_state(State::Nack);
-                let sent = critical_section::with(|cs| bridge.reset_txbuf(cs));
-                bridge.notify(Event::Notification(Notification::Sent { sent }));
+                return bridge.fail(Reason::I2C(I2CError::Underrun))
             }
-            State::Idle | State::Rx | State::Nack => {
-                return bridge.fail(Reason::Protocol(ProtocolError::NackWhileNotTranseiving))
+            _ if bridge.is_recoverable(I2CError::Overrun) => {
+                recover(regs, bridge, I2CError::Overrun)
             }
+            _ => return bridge.fail(Reason::I2C(I2CError::Overrun)),
         }
     }
 
@@ -159,7 +860,11 @@ pub fn handle_error_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
         ($name:ident NONE) => {};
 
         ($name:ident $err:ident) => {
-            bridge.fail(Reason::I2C(I2CError::$err));
+            if bridge.is_recoverable(I2CError::$err) {
+                recover(regs, bridge, I2CError::$err);
+            } else {
+                bridge.fail(Reason::I2C(I2CError::$err));
+            }
         };
     }
 
@@ -167,7 +872,7 @@ pub fn handle_error_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
         ($name:ident NONE) => {};
 
         ($name:ident $set_func:ident) => {
-            regs.sr1().modify(|w| w.$set_func(false));
+            regs.modify_sr1(|w| w.$set_func(false));
         };
     }
 
@@ -183,9 +888,8 @@ pub fn handle_error_interrupt<T: i2c::Instance, B: InterruptBridge<T>>(bridge: &
     }
 
     handle_errors!([
-        (berr, set_berr, NONE),
+        (berr, set_berr, BusError),
         (arlo, set_arlo, ArbitrationLoss),
-        (ovr, set_ovr, Overrun),
         (pecerr, set_pecerr, PecError),
         (timeout, set_timeout, Timeout),
         (alert, set_alert, SmBusAlert)
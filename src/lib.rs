@@ -1,17 +1,45 @@
 #![no_std]
-#![feature(async_fn_in_trait)]
+
+use core::fmt;
+
+// Has to come first, the macros are only visible to the modules declared after it.
+mod diag;
 
 mod bridge;
+mod bridge_core;
+mod config;
 mod interrupts;
+#[cfg(feature = "testing")]
+mod mock;
 mod receive_buffer;
+mod register_map;
+mod register_slave;
 mod send_buffer;
 mod slave;
 mod state_holder;
+#[cfg(feature = "stream")]
+mod stream;
 mod tx_lock;
 
-pub use bridge::Bridge;
-pub use interrupts::{handle_error_interrupt, handle_event_interrupt};
-pub use slave::{I2CSlave, I2cBridge};
+#[cfg(feature = "profiling")]
+pub use bridge::IsrTiming;
+#[cfg(feature = "stats")]
+pub use bridge::StatsSnapshot;
+pub use bridge::{Bridge, InterruptChannel};
+pub use config::Config;
+#[cfg(feature = "dma")]
+pub use interrupts::handle_dma_interrupt;
+pub use interrupts::{handle_error_interrupt, handle_event_interrupt, InterruptBridge};
+#[cfg(feature = "testing")]
+pub use mock::{MockBridge, Snapshot};
+pub use receive_buffer::{ReceiveBuffer, RxSink};
+pub use register_map::{RegisterMap, RegisterRollover, RegisterSource};
+pub use register_slave::{RegisterSlave, MAX_REGISTER_HOOKS};
+pub use send_buffer::{SendBuffer, TxSource};
+pub use slave::{compute_ccr, I2CSlave, I2cBridge, MaskGuard};
+#[cfg(feature = "stream")]
+pub use stream::EventStream;
+pub use tx_lock::TxLockType;
 
 #[cfg(feature = "dump")]
 use bridge::StateDump;
@@ -27,32 +55,139 @@ pub enum State {
     Nack,
 }
 
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// How the data is passed between the interrupt handler and the consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Mode {
+    /// Writes are collected in the receive buffer, reads are served from the send buffer.
+    Buffered,
+    /// Every received byte is emitted as `Control::RxByte`, the receive buffer stays empty.
+    /// Reads emit `Control::TxByte` whenever the send buffer runs empty.
+    RawByte,
+}
+
+/// Which ends of a write emit `Control::Received`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReceivedOn {
+    /// Both the repeated start into a read and the final STOP.
+    Both,
+    /// Only the STOP that ends a write.
+    Stop,
+    /// Only the repeated start that turns a write into a read.
+    Restart,
+}
+
+/// What the master reads once the send buffer has run empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Underrun {
+    /// SCL is stretched until the consumer writes more.
+    Stretch,
+    /// The byte is sent, like `0xFF` for an idle bus or `0x00`.
+    Fill(u8),
+    /// The last byte of the read is sent again, `0xFF` if there was none.
+    RepeatLast,
+}
+
+/// The second byte of a general call, as defined by the I2C specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GeneralCallCommand {
+    /// `0x06`, reset and write the programmable part of the address.
+    Reset,
+    /// `0x04`, write the programmable part of the address without a reset.
+    WriteAddress,
+    Other(u8),
+}
+
+impl From<u8> for GeneralCallCommand {
+    fn from(byte: u8) -> Self {
+        match byte {
+            0x06 => GeneralCallCommand::Reset,
+            0x04 => GeneralCallCommand::WriteAddress,
+            byte => GeneralCallCommand::Other(byte),
+        }
+    }
+}
+
+/// Address the master used to select the slave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bytemuck::NoUninit)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum MatchedAddress {
+    Primary,
+    /// [`Config::own_address2`], only matched in dual-address mode.
+    Secondary,
+    /// Address 0, always answered on top of the own addresses.
+    GeneralCall,
+}
+
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum Notification {
     Addr { tx: bool, gencall: bool },
     Sent { sent: usize },
     Stop,
+    Recovered { error: I2CError },
+    AlertResponse,
+    Nacked,
+    QuickCommand { write: bool },
 }
 
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum Control {
-    Received { size: usize, write: bool },
-    TxEmpty { initial: bool },
+    Received {
+        size: usize,
+        write: bool,
+        address: MatchedAddress,
+    },
+    TxEmpty {
+        initial: bool,
+        address: MatchedAddress,
+    },
+    RxByte(u8),
+    TxByte,
+    GeneralCall {
+        size: usize,
+        write: bool,
+    },
+    ReceivedInline {
+        data: [u8; INLINE_RECEIVED_SIZE],
+        len: u8,
+        write: bool,
+        address: MatchedAddress,
+    },
 }
 
+/// Longest write carried in `Control::ReceivedInline`, see
+/// [`Bridge::with_inline_received`].
+pub const INLINE_RECEIVED_SIZE: usize = 4;
+
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum Event {
     Notification(Notification),
     Control(Control),
 }
 
+// New variants go last, see the bit mask in `Bridge::with_recoverable`.
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum I2CError {
-    #[allow(dead_code)]
     BusError,
     ArbitrationLoss,
     #[allow(dead_code)]
@@ -61,12 +196,14 @@ pub enum I2CError {
     PecError,
     Timeout,
     SmBusAlert,
+    Underrun,
 }
 
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum ProtocolError {
-    RxneAndTxne,
+    RxneAndTxne { state: State },
     AddrDuringTransmission,
     RxneWhileNotReceiving,
     TxeWhileNotTranseiving,
@@ -76,6 +213,7 @@ pub enum ProtocolError {
 
 #[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum Reason {
     I2C(I2CError),
     Protocol(ProtocolError),
@@ -89,3 +227,113 @@ pub struct Error {
     pub dump: StateDump,
     pub reason: Reason,
 }
+
+/// Reason [`I2CSlave::try_new`] rejected its arguments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    AddressOutOfRange,
+    SpeedNotSupported,
+    ClockTooSlow,
+    FilterNotSupported,
+    RiseTimeTooLong,
+}
+
+/// Returned by [`I2CSlave::write_all`] when `buf` did not fit into the send buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SendError {
+    /// Bytes from the start of `buf` that were queued anyway.
+    pub written: usize,
+}
+
+/// Status bits of SR2, returned by [`I2CSlave::hw_status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HwStatus {
+    /// Communication on the bus, from START until STOP.
+    pub busy: bool,
+    /// Master mode, never expected to be set on a slave.
+    pub msl: bool,
+    /// The current transfer is a read, the slave transmits.
+    pub tra: bool,
+    /// The general call address was matched.
+    pub gencall: bool,
+    /// [`Config::own_address2`] was matched.
+    pub dualf: bool,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "send buffer full after {} bytes", self.written)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ConfigError::AddressOutOfRange => "own address is out of range",
+            ConfigError::SpeedNotSupported => "speed must be above 0 and at most 100kHz",
+            ConfigError::ClockTooSlow => "bus frequency in Standard Mode must be at least 2MHz",
+            ConfigError::FilterNotSupported => {
+                "noise filters are not configurable on this peripheral"
+            }
+            ConfigError::RiseTimeTooLong => "rise time does not fit into TRISE",
+        })
+    }
+}
+
+impl fmt::Display for I2CError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            I2CError::BusError => "bus error",
+            I2CError::ArbitrationLoss => "arbitration lost",
+            I2CError::AcknowledgeFailure => "acknowledge failure",
+            I2CError::Overrun => "overrun",
+            I2CError::Underrun => "underrun",
+            I2CError::PecError => "PEC error",
+            I2CError::Timeout => "timeout",
+            I2CError::SmBusAlert => "SMBus alert",
+        })
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProtocolError::RxneAndTxne { state } => {
+                return write!(f, "RXNE and TXE set at the same time in {:?}", state)
+            }
+            ProtocolError::AddrDuringTransmission => "address matched during transmission",
+            ProtocolError::RxneWhileNotReceiving => "RXNE while not receiving",
+            ProtocolError::TxeWhileNotTranseiving => "TXE while not transmitting",
+            ProtocolError::StopDuringTransmission => "STOP during transmission",
+            ProtocolError::NackWhileNotTranseiving => "NACK while not transmitting",
+        })
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::I2C(err) => write!(f, "I2C error: {}", err),
+            Reason::Protocol(err) => write!(f, "protocol error: {}", err),
+            Reason::ReceiveBufferFull => f.write_str("receive buffer is full"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)?;
+
+        #[cfg(feature = "dump")]
+        write!(
+            f,
+            " (state: {:?}, states: {:?}, events: {:?})",
+            self.dump.current_state, self.dump.state_history, self.dump.event_history
+        )?;
+
+        Ok(())
+    }
+}
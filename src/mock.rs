@@ -0,0 +1,1226 @@
+use core::{
+    cell::{Cell, RefCell},
+    task::{Context, Poll},
+};
+
+use critical_section::{CriticalSection, Mutex};
+use embassy_stm32::i2c;
+#[cfg(feature = "dma")]
+use embassy_stm32::pac::i2c::regs::Cr2;
+use embassy_stm32::pac::i2c::regs::{Cr1, Oar1, Sr1, Sr2};
+use embassy_stm32::pac::i2c::vals::Addmode;
+use heapless::{Deque, Vec};
+
+#[cfg(feature = "dump")]
+use super::{
+    bridge::{StateDump, EVENTS_HISTORY_SIZE, STATES_HISTORY_SIZE},
+    Notification,
+};
+use super::{
+    bridge_core::BridgeCore,
+    interrupts::{self, InterruptBridge, Registers},
+    receive_buffer::ReceiveBuffer,
+    send_buffer::SendBuffer,
+    slave::I2cBridge,
+    tx_lock::{Lock, TxLockType},
+    Error, Event, I2CError, MatchedAddress, Mode, Reason, State,
+};
+
+/// Status register values seen by one run of the interrupt handlers, see
+/// [`MockBridge::replay`].
+#[derive(Clone, Copy, Default)]
+pub struct Snapshot {
+    pub sr1: Sr1,
+    pub sr2: Sr2,
+    /// Value of DR, the received byte for RXNE.
+    pub dr: u8,
+}
+
+impl Snapshot {
+    /// Address match, of a read if `tx` is set.
+    pub fn addr(tx: bool) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.sr1.set_addr(true);
+        snapshot.sr2.set_tra(tx);
+        snapshot
+    }
+
+    /// Address match of a write to the general call address.
+    pub fn general_call() -> Self {
+        let mut snapshot = Self::addr(false);
+        snapshot.sr2.set_gencall(true);
+        snapshot
+    }
+
+    /// `byte` received.
+    pub fn rxne(byte: u8) -> Self {
+        let mut snapshot = Self::default();
+        snapshot.sr1.set_rxne(true);
+        snapshot.dr = byte;
+        snapshot
+    }
+
+    /// DR and the shift register empty, the master clocks out the next byte.
+    pub fn txe() -> Self {
+        let mut snapshot = Self::default();
+        snapshot.sr1.set_txe(true);
+        snapshot.sr1.set_btf(true);
+        snapshot
+    }
+
+    /// The NACK ending a read.
+    pub fn nack() -> Self {
+        let mut snapshot = Self::default();
+        snapshot.sr1.set_af(true);
+        snapshot
+    }
+
+    pub fn stop() -> Self {
+        Self::default().with_stop()
+    }
+
+    /// Adds a pending STOP.
+    pub fn with_stop(mut self) -> Self {
+        self.sr1.set_stopf(true);
+        self
+    }
+
+    fn has_event(&self) -> bool {
+        let sr1 = self.sr1;
+        sr1.addr() || sr1.btf() || sr1.rxne() || sr1.txe() || sr1.stopf()
+    }
+
+    fn has_error(sr1: Sr1) -> bool {
+        sr1.af()
+            || sr1.ovr()
+            || sr1.berr()
+            || sr1.arlo()
+            || sr1.pecerr()
+            || sr1.timeout()
+            || sr1.alert()
+    }
+}
+
+struct MockRegisters<const N: usize> {
+    sr1: Mutex<Cell<Sr1>>,
+    sr2: Mutex<Cell<Sr2>>,
+    cr1: Mutex<Cell<Cr1>>,
+    #[cfg(feature = "dma")]
+    cr2: Mutex<Cell<Cr2>>,
+    oar1: Mutex<Cell<Oar1>>,
+    dr: Mutex<Cell<u8>>,
+    /// Bytes written to DR.
+    sent: Mutex<RefCell<Vec<u8, N>>>,
+}
+
+impl<const N: usize> MockRegisters<N> {
+    const fn new() -> Self {
+        Self {
+            sr1: Mutex::new(Cell::new(Sr1(0))),
+            sr2: Mutex::new(Cell::new(Sr2(0))),
+            cr1: Mutex::new(Cell::new(Cr1(0))),
+            #[cfg(feature = "dma")]
+            cr2: Mutex::new(Cell::new(Cr2(0))),
+            oar1: Mutex::new(Cell::new(Oar1(0))),
+            dr: Mutex::new(Cell::new(0)),
+            sent: Mutex::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    fn load(&self, snapshot: &Snapshot) {
+        critical_section::with(|cs| {
+            self.sr1.borrow(cs).set(snapshot.sr1);
+            self.sr2.borrow(cs).set(snapshot.sr2);
+            self.dr.borrow(cs).set(snapshot.dr);
+        })
+    }
+}
+
+fn modify<R: Copy>(reg: &Mutex<Cell<R>>, f: impl FnOnce(&mut R)) {
+    critical_section::with(|cs| {
+        let mut value = reg.borrow(cs).get();
+        f(&mut value);
+        reg.borrow(cs).set(value);
+    })
+}
+
+impl<const N: usize> Registers for MockRegisters<N> {
+    fn sr1(&self) -> Sr1 {
+        critical_section::with(|cs| self.sr1.borrow(cs).get())
+    }
+
+    fn modify_sr1(&self, f: impl FnOnce(&mut Sr1)) {
+        modify(&self.sr1, f)
+    }
+
+    fn sr2(&self) -> Sr2 {
+        critical_section::with(|cs| self.sr2.borrow(cs).get())
+    }
+
+    fn cr1(&self) -> Cr1 {
+        critical_section::with(|cs| self.cr1.borrow(cs).get())
+    }
+
+    fn modify_cr1(&self, f: impl FnOnce(&mut Cr1)) {
+        modify(&self.cr1, f)
+    }
+
+    #[cfg(feature = "dma")]
+    fn cr2(&self) -> Cr2 {
+        critical_section::with(|cs| self.cr2.borrow(cs).get())
+    }
+
+    #[cfg(feature = "dma")]
+    fn modify_cr2(&self, f: impl FnOnce(&mut Cr2)) {
+        modify(&self.cr2, f)
+    }
+
+    fn oar1(&self) -> Oar1 {
+        critical_section::with(|cs| self.oar1.borrow(cs).get())
+    }
+
+    fn read_dr(&self) -> u8 {
+        critical_section::with(|cs| self.dr.borrow(cs).get())
+    }
+
+    fn write_dr(&self, byte: u8) {
+        critical_section::with(|cs| {
+            self.sent
+                .borrow_ref_mut(cs)
+                .push(byte)
+                .expect("Mock DR log is full")
+        })
+    }
+
+    #[cfg(feature = "dma")]
+    fn dr_address(&self) -> u32 {
+        0
+    }
+}
+
+/// Stands in for the interrupt masking of [`TxLock`](crate::tx_lock::TxLock).
+struct MockLock {
+    locked: Mutex<Cell<bool>>,
+}
+
+impl Lock for MockLock {
+    fn lock(&self, _lock_type: TxLockType) {
+        critical_section::with(|cs| self.locked.borrow(cs).set(true))
+    }
+
+    fn unlock(&self) {
+        critical_section::with(|cs| self.locked.borrow(cs).set(false))
+    }
+}
+
+type AddrHook = fn(bool, bool) -> bool;
+
+/// Hardware-free bridge for testing consumer code on the host.
+///
+/// It implements both [`I2cBridge`] and [`InterruptBridge`]. Transactions are played by
+/// running the interrupt handlers on scripted register values, with [`MockBridge::replay`]
+/// or the `play_*` fixtures built on it, and the response is taken with
+/// [`MockBridge::master_read`]. Receiving with the queue empty stays pending until the next
+/// step has been played, consumer futures are driven by polling them in between.
+///
+/// The buffers and the state are handled by the same code as in [`Bridge`](crate::Bridge),
+/// only the registers are scripted.
+pub struct MockBridge<const N: usize> {
+    core: BridgeCore<SendBuffer<N>, ReceiveBuffer<N>, MockLock>,
+    events: Mutex<RefCell<Deque<Result<Event, Error>, N>>>,
+    addr_hook: Mutex<Cell<Option<AddrHook>>>,
+    regs: MockRegisters<N>,
+}
+
+impl<const N: usize> MockBridge<N> {
+    pub const fn new() -> Self {
+        Self {
+            core: BridgeCore::new(MockLock {
+                locked: Mutex::new(Cell::new(false)),
+            }),
+            events: Mutex::new(RefCell::new(Deque::new())),
+            addr_hook: Mutex::new(Cell::new(None)),
+            regs: MockRegisters::new(),
+        }
+    }
+
+    /// Plays a bridge set up with [`Bridge::with_backpressure`](crate::Bridge::with_backpressure).
+    pub const fn with_backpressure(mut self) -> Self {
+        self.core.backpressure = true;
+        self
+    }
+
+    pub fn push_event(&self, event: Result<Event, Error>) {
+        critical_section::with(|cs| {
+            self.events
+                .borrow_ref_mut(cs)
+                .push_back(event)
+                .expect("Mock event queue is full")
+        })
+    }
+
+    pub fn is_tx_locked(&self) -> bool {
+        critical_section::with(|cs| self.core.tx_lock.locked.borrow(cs).get())
+    }
+
+    /// Stores `data` in the receive buffer as if the master had written it.
+    pub fn master_write(&self, data: &[u8]) {
+        critical_section::with(|cs| {
+            let mut rb = self.core.receive_buffer.borrow_ref_mut(cs);
+            for &byte in data {
+                rb.write_byte(byte).expect("Mock receive buffer is full");
+            }
+        })
+    }
+
+    /// Runs the interrupt handlers once for each snapshot, the event handler if an event flag
+    /// is set and then the error handler if an error flag is left.
+    pub fn replay<T: i2c::Instance>(&self, snapshots: &[Snapshot]) {
+        for snapshot in snapshots {
+            self.regs.load(snapshot);
+
+            if snapshot.has_event() {
+                interrupts::event_interrupt::<T, _, _>(&self.regs, self);
+            }
+
+            if Snapshot::has_error(self.regs.sr1()) {
+                interrupts::error_interrupt::<T, _, _>(&self.regs, self);
+            }
+        }
+    }
+
+    /// Plays a bridge set up with [`Bridge::with_addr_hook`](crate::Bridge::with_addr_hook).
+    pub fn set_addr_hook(&self, hook: Option<AddrHook>) {
+        critical_section::with(|cs| self.addr_hook.borrow(cs).set(hook))
+    }
+
+    /// Plays the peripheral set up with [`Config::no_stretch`](crate::Config::no_stretch).
+    pub fn set_no_stretch(&self, no_stretch: bool) {
+        modify(&self.regs.cr1, |w| w.set_nostretch(no_stretch));
+    }
+
+    /// Plays the peripheral set up with the 7-bit own address `address`.
+    #[cfg(feature = "smbus")]
+    pub fn set_own_address(&self, address: u8) {
+        modify(&self.regs.oar1, |w| w.set_add((address as u16) << 1));
+    }
+
+    /// Plays the peripheral set up with the 10-bit own address `address`, see
+    /// [`Config::ten_bit_address`](crate::Config::ten_bit_address).
+    pub fn set_ten_bit_address(&self, address: u16) {
+        modify(&self.regs.oar1, |w| {
+            w.set_addmode(Addmode::BIT10);
+            w.set_add(address);
+        });
+    }
+
+    /// Plays [`I2CSlave::assert_smbus_alert`](crate::I2CSlave::assert_smbus_alert) and
+    /// [`I2CSlave::deassert_smbus_alert`](crate::I2CSlave::deassert_smbus_alert).
+    #[cfg(feature = "smbus")]
+    pub fn set_smbus_alert(&self, alert: bool) {
+        modify(&self.regs.cr1, |w| w.set_alert(alert));
+    }
+
+    /// Plays a write of `data` to the primary address, ended by a STOP.
+    pub fn play_write<T: i2c::Instance>(&self, data: &[u8]) {
+        self.replay::<T>(&[Snapshot::addr(false)]);
+        for &byte in data {
+            self.replay::<T>(&[Snapshot::rxne(byte)]);
+        }
+        self.replay::<T>(&[Snapshot::stop()]);
+    }
+
+    /// Plays the start of a read from the primary address, up to the first byte. The rest is
+    /// taken with [`MockBridge::master_read`], once the consumer has answered the `TxEmpty`
+    /// if there was nothing to send.
+    pub fn play_read<T: i2c::Instance>(&self) {
+        self.replay::<T>(&[Snapshot::addr(true), Snapshot::txe()]);
+    }
+
+    /// Plays a write of `data` followed by a read with repeated start, as for a register read.
+    pub fn play_write_read<T: i2c::Instance>(&self, data: &[u8]) {
+        self.replay::<T>(&[Snapshot::addr(false)]);
+        for &byte in data {
+            self.replay::<T>(&[Snapshot::rxne(byte)]);
+        }
+        self.play_read::<T>();
+    }
+
+    /// Clocks bytes out of the slave until `n` have gone out since the last call or the slave
+    /// stretches SCL for more data, and returns them. Once all `n` are out, the read is ended
+    /// with a NACK and a STOP.
+    pub fn master_read<T: i2c::Instance>(&self, n: usize) -> Vec<u8, N> {
+        let sent = || critical_section::with(|cs| self.regs.sent.borrow_ref(cs).len());
+
+        while sent() < n && !self.is_tx_locked() {
+            self.replay::<T>(&[Snapshot::txe()]);
+        }
+
+        if sent() >= n {
+            self.replay::<T>(&[Snapshot::nack(), Snapshot::stop()]);
+        }
+
+        critical_section::with(|cs| {
+            core::mem::replace(&mut *self.regs.sent.borrow_ref_mut(cs), Vec::new())
+        })
+    }
+}
+
+impl<T: i2c::Instance, const N: usize> I2cBridge<T> for MockBridge<N> {
+    const TX_CAPACITY: usize = N;
+
+    const RX_CAPACITY: usize = N;
+
+    async fn receive(&self) -> Result<Event, Error> {
+        core::future::poll_fn(|cx| I2cBridge::<T>::poll_receive(self, cx)).await
+    }
+
+    fn try_receive(&self) -> Option<Result<Event, Error>> {
+        critical_section::with(|cs| self.events.borrow_ref_mut(cs).pop_front())
+    }
+
+    // Nothing wakes the task, the test polls again after playing the next step.
+    fn poll_receive(&self, _cx: &mut Context<'_>) -> Poll<Result<Event, Error>> {
+        match I2cBridge::<T>::try_receive(self) {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+
+    fn state(&self) -> State {
+        self.core.state()
+    }
+
+    fn write<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        self.core.write(cs, buf)
+    }
+
+    fn clear_tx(&self, cs: CriticalSection) {
+        self.core.clear_tx(cs)
+    }
+
+    fn stage_tx<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        self.core.stage_tx(cs, buf)
+    }
+
+    fn tx_remaining(&self, cs: CriticalSection) -> usize {
+        self.core.tx_remaining(cs)
+    }
+
+    fn read(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        self.core.read(cs, buf)
+    }
+
+    fn read_some(&self, cs: CriticalSection, buf: &mut [u8]) -> usize {
+        self.core.read_some(cs, buf)
+    }
+
+    fn read_with_crc(
+        &self,
+        cs: CriticalSection,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, u8)>, usize> {
+        self.core.read_with_crc(cs, buf)
+    }
+
+    fn last_write(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        self.core.last_write(cs, buf)
+    }
+
+    #[cfg(feature = "frames")]
+    fn read_frame(&self, _cs: CriticalSection, _buf: &mut [u8]) -> Option<Result<usize, usize>> {
+        None
+    }
+
+    fn last_pec(&self) -> u8 {
+        self.core.last_pec()
+    }
+
+    fn last_error(&self) -> Option<Error> {
+        self.core.last_error()
+    }
+
+    fn matched_address(&self) -> MatchedAddress {
+        self.core.matched_address()
+    }
+
+    fn ack_processed(&self) {
+        self.core.ack_processed()
+    }
+
+    fn set_disable_on_error(&self, _disable: bool) {}
+
+    fn set_responsive(&self, _responsive: bool) {}
+
+    fn is_responsive(&self) -> bool {
+        true
+    }
+
+    #[cfg(feature = "dma")]
+    fn set_tx_dma(&self, _enable: bool) {}
+
+    #[cfg(feature = "dma")]
+    fn set_rx_dma(&self, _enable: bool) {}
+
+    #[cfg(feature = "async-write")]
+    async fn wait_txbuf_drained(&self) {}
+
+    #[cfg(feature = "async-write")]
+    fn reset_txbuf_drained(&self) {}
+
+    fn reset(&self, cs: CriticalSection) {
+        self.core.reset(cs)
+    }
+
+    #[cfg(feature = "history")]
+    fn clear_history(&self, _cs: CriticalSection) {}
+}
+
+impl<T: i2c::Instance, const N: usize> InterruptBridge<T> for MockBridge<N> {
+    fn get_state(&self) -> State {
+        self.core.state()
+    }
+
+    fn mode(&self) -> Mode {
+        Mode::Buffered
+    }
+
+    fn underrun_byte(&self) -> Option<u8> {
+        None
+    }
+
+    fn set_state(&self, state: State) {
+        self.core.set_state(state)
+    }
+
+    fn fail(&self, reason: Reason) {
+        let error = Error {
+            #[cfg(feature = "dump")]
+            dump: StateDump {
+                state_history: [State::Idle; STATES_HISTORY_SIZE],
+                current_state: InterruptBridge::<T>::get_state(self),
+                event_history: [Event::Notification(Notification::Stop); EVENTS_HISTORY_SIZE],
+            },
+            reason,
+        };
+
+        self.core.set_error(error);
+        self.push_event(Err(error))
+    }
+
+    fn notify(&self, event: Event) {
+        critical_section::with(|cs| self.core.notify(cs, event, true));
+        self.push_event(Ok(event))
+    }
+
+    fn lock_tx(&self, lock_type: TxLockType) {
+        self.core.lock_tx(lock_type)
+    }
+
+    fn unlock_tx(&self) {
+        self.core.unlock_tx()
+    }
+
+    fn get_rxbuf_size(&self, cs: CriticalSection) -> usize {
+        self.core.get_rxbuf_size(cs)
+    }
+
+    fn write_rxbuf_byte(&self, cs: CriticalSection, byte: u8) -> Result<(), ()> {
+        self.core.write_rxbuf_byte(cs, byte)
+    }
+
+    fn take_rxbuf_high_water(&self, cs: CriticalSection) -> bool {
+        self.core.take_rxbuf_high_water(cs)
+    }
+
+    #[cfg(feature = "frames")]
+    fn end_frame(&self, _cs: CriticalSection) {}
+
+    fn pop_txbuf_byte(&self, cs: CriticalSection) -> Option<u8> {
+        self.core.pop_txbuf_byte(cs)
+    }
+
+    fn promote_txbuf(&self, cs: CriticalSection) {
+        self.core.promote_txbuf(cs)
+    }
+
+    fn fill_txbuf(&self, _cs: CriticalSection, _initial: bool) -> bool {
+        false
+    }
+
+    fn is_txbuf_empty(&self, cs: CriticalSection) -> bool {
+        self.core.is_txbuf_empty(cs)
+    }
+
+    #[cfg(feature = "dma")]
+    fn tx_dma_channel(&self) -> Option<usize> {
+        None
+    }
+
+    #[cfg(feature = "dma")]
+    fn start_txbuf_dma(&self, _cs: CriticalSection) -> Option<(*const u8, usize)> {
+        None
+    }
+
+    #[cfg(feature = "dma")]
+    fn end_txbuf_dma(&self, _cs: CriticalSection, _remaining: usize) {}
+
+    #[cfg(feature = "dma")]
+    fn rx_dma_channel(&self) -> Option<usize> {
+        None
+    }
+
+    #[cfg(feature = "dma")]
+    fn start_rxbuf_dma(&self, _cs: CriticalSection) -> Option<(*mut u8, usize)> {
+        None
+    }
+
+    #[cfg(feature = "dma")]
+    fn end_rxbuf_dma(&self, _cs: CriticalSection, _remaining: usize) {}
+
+    fn reset_txbuf(&self, cs: CriticalSection) -> usize {
+        self.core.reset_txbuf(cs)
+    }
+
+    fn reset_rxbuf(&self, cs: CriticalSection) {
+        self.core.reset_rxbuf(cs)
+    }
+
+    fn set_pec(&self, pec: u8) {
+        self.core.set_pec(pec)
+    }
+
+    fn get_matched_address(&self) -> MatchedAddress {
+        self.core.matched_address()
+    }
+
+    fn set_matched_address(&self, address: MatchedAddress) {
+        self.core.set_matched_address(address)
+    }
+
+    fn hold_addr(&self) -> bool {
+        self.core.hold_addr()
+    }
+
+    fn set_transferred(&self, transferred: bool) {
+        self.core.set_transferred(transferred)
+    }
+
+    fn transferred(&self) -> bool {
+        self.core.transferred()
+    }
+
+    fn ack_address(&self) -> bool {
+        true
+    }
+
+    fn accept_addr(&self, tx: bool, gencall: bool) -> bool {
+        critical_section::with(|cs| self.addr_hook.borrow(cs).get())
+            .map_or(true, |hook| hook(tx, gencall))
+    }
+
+    fn set_read_refused(&self, refused: bool) {
+        self.core.set_read_refused(refused)
+    }
+
+    fn read_refused(&self) -> bool {
+        self.core.read_refused()
+    }
+
+    fn stop_detected(&self) {}
+
+    #[cfg(feature = "smbus")]
+    fn respond_alert(&self, cs: CriticalSection, address: u8) {
+        self.core.respond_alert(cs, address)
+    }
+
+    fn is_recoverable(&self, _err: I2CError) -> bool {
+        false
+    }
+
+    #[cfg(feature = "profiling")]
+    fn record_isr_cycles(&self, _cycles: u32) {}
+
+    #[cfg(feature = "stats")]
+    fn count_spurious_interrupt(&self) {}
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use core::{
+        future::Future,
+        pin::pin,
+        task::{RawWaker, RawWakerVTable, Waker},
+    };
+
+    use embassy_stm32::peripherals::I2C1;
+
+    use super::*;
+    use crate::{Control, I2CSlave, Notification, RegisterSlave};
+
+    macro_rules! assert_next {
+        ($mock:expr, $pattern:pat) => {
+            let event = I2cBridge::<I2C1>::try_receive($mock);
+            assert!(matches!(event, Some(Ok($pattern))), "{:?}", event);
+        };
+    }
+
+    /// Consumer with four registers, written as `[index, data..]` and read from the index of
+    /// the last write.
+    #[derive(Default)]
+    struct Device {
+        regs: [u8; 4],
+        index: usize,
+    }
+
+    impl Device {
+        fn serve(&mut self, mock: &MockBridge<16>) {
+            while let Some(event) = I2cBridge::<I2C1>::try_receive(mock) {
+                match event.unwrap() {
+                    Event::Control(Control::Received { .. }) => {
+                        let mut buf = [0u8; 16];
+                        let size = critical_section::with(|cs| {
+                            I2cBridge::<I2C1>::read(mock, cs, &mut buf)
+                        })
+                        .unwrap();
+
+                        if let Some((&index, data)) = buf[..size].split_first() {
+                            self.index = index as usize % self.regs.len();
+                            for (i, &byte) in data.iter().enumerate() {
+                                self.regs[(self.index + i) % self.regs.len()] = byte;
+                            }
+                        }
+                    }
+                    Event::Control(Control::TxEmpty { .. }) => {
+                        critical_section::with(|cs| {
+                            I2cBridge::<I2C1>::write(mock, cs, &self.regs[self.index..])
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn assert_drained(mock: &MockBridge<16>) {
+        let event = I2cBridge::<I2C1>::try_receive(mock);
+        assert!(event.is_none(), "{:?}", event);
+    }
+
+    /// Polls `fut` once, it handles the events queued so far and stops at the first missing one.
+    fn poll_once<F: Future>(fut: core::pin::Pin<&mut F>) -> Poll<F::Output> {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(|_| RAW, |_| {}, |_| {}, |_| {});
+        const RAW: RawWaker = RawWaker::new(core::ptr::null(), &VTABLE);
+
+        // SAFETY: the vtable functions do nothing, the data pointer is never used.
+        let waker = unsafe { Waker::from_raw(RAW) };
+        fut.poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn simple_write() {
+        let mock = MockBridge::<16>::new();
+        mock.play_write::<I2C1>(&[1, 2, 3]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::Received {
+                size: 3,
+                write: false,
+                ..
+            })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+
+        let mut buf = [0u8; 3];
+        let read = critical_section::with(|cs| I2cBridge::<I2C1>::read(&mock, cs, &mut buf));
+        assert_eq!(read, Ok(3));
+        assert_eq!(buf, [1, 2, 3]);
+    }
+
+    #[test]
+    fn empty_write_is_quick_command() {
+        let mock = MockBridge::<16>::new();
+        mock.play_write::<I2C1>(&[]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(&mock, Event::Control(Control::Received { size: 0, .. }));
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::QuickCommand { write: true })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn simple_read() {
+        let mock = MockBridge::<16>::new();
+        mock.play_read::<I2C1>();
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: true, .. })
+        );
+        assert!(mock.is_tx_locked());
+
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[0xAA, 0xBB]));
+        assert_eq!(mock.master_read::<I2C1>(2), [0xAA, 0xBB]);
+
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 2 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn write_then_read_with_restart() {
+        let mock = MockBridge::<16>::new();
+        mock.play_write_read::<I2C1>(&[0x10]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::Received {
+                size: 1,
+                write: true,
+                ..
+            })
+        );
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: true, .. })
+        );
+        assert_drained(&mock);
+
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[0x42]));
+        assert_eq!(mock.master_read::<I2C1>(1), [0x42]);
+
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn register_read() {
+        let mock = MockBridge::<16>::new();
+        let mut device = Device {
+            regs: [10, 20, 30, 40],
+            index: 0,
+        };
+
+        mock.play_write_read::<I2C1>(&[2]);
+        device.serve(&mock);
+        assert_eq!(mock.master_read::<I2C1>(2), [30, 40]);
+
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 2 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn register_write_then_read() {
+        let mock = MockBridge::<16>::new();
+        let mut device = Device::default();
+
+        mock.play_write::<I2C1>(&[1, 0x55, 0x66]);
+        device.serve(&mock);
+        assert_eq!(device.regs, [0, 0x55, 0x66, 0]);
+
+        mock.play_write_read::<I2C1>(&[1]);
+        device.serve(&mock);
+        assert_eq!(mock.master_read::<I2C1>(2), [0x55, 0x66]);
+        device.serve(&mock);
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn stop_and_addr_together() {
+        let mock = MockBridge::<16>::new();
+        mock.replay::<I2C1>(&[Snapshot::addr(false), Snapshot::rxne(1)]);
+        // The STOP of the write is still pending at the address match of the next read.
+        mock.replay::<I2C1>(&[Snapshot::addr(true).with_stop(), Snapshot::txe()]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::Received {
+                size: 1,
+                write: false,
+                ..
+            })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: true, .. })
+        );
+        assert_drained(&mock);
+        assert!(matches!(I2cBridge::<I2C1>::state(&mock), State::TxInitial));
+    }
+
+    #[test]
+    fn write_after_data_ran_out_is_queued() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[1]));
+        mock.play_read::<I2C1>();
+
+        // Nothing was asked for, the master may be about to NACK.
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[2]));
+        assert_eq!(mock.master_read::<I2C1>(1), [1]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(1), [2]);
+    }
+
+    #[test]
+    fn write_answering_tx_empty_extends_read() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[1]));
+        mock.play_read::<I2C1>();
+
+        assert_eq!(mock.master_read::<I2C1>(2), [1]);
+        assert!(mock.is_tx_locked());
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[2]));
+        assert_eq!(mock.master_read::<I2C1>(1), [2]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: false, .. })
+        );
+        // Both bytes count, although the second one went out of a refilled send buffer.
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 2 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn refilled_read_counted_at_stop() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[1]));
+        mock.play_read::<I2C1>();
+        mock.replay::<I2C1>(&[Snapshot::txe()]);
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[2, 3]));
+        mock.replay::<I2C1>(&[Snapshot::txe(), Snapshot::txe()]);
+        // The NACK is still pending in the error interrupt when the STOP is handled.
+        mock.replay::<I2C1>(&[Snapshot::nack().with_stop()]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: false, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 3 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn ten_bit_read_reports_no_write() {
+        let mock = MockBridge::<16>::new();
+        mock.set_ten_bit_address(0x2A5);
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[0x11, 0x22]));
+
+        // The header and the low address byte are written, the header is repeated for the read.
+        mock.replay::<I2C1>(&[Snapshot::addr(false)]);
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(2), [0x11, 0x22]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 2 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn ten_bit_answers_general_call() {
+        let mock = MockBridge::<16>::new();
+        mock.set_ten_bit_address(0x2A5);
+
+        mock.replay::<I2C1>(&[
+            Snapshot::general_call(),
+            Snapshot::rxne(0x06),
+            Snapshot::stop(),
+        ]);
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr {
+                tx: false,
+                gencall: true
+            })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::Received {
+                size: 1,
+                write: false,
+                address: MatchedAddress::GeneralCall,
+            })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+
+        let mut buf = [0u8; 1];
+        let read = critical_section::with(|cs| I2cBridge::<I2C1>::read(&mock, cs, &mut buf));
+        assert_eq!((read, buf), (Ok(1), [0x06]));
+
+        // A register read on the own address right after still reports the index.
+        mock.play_write_read::<I2C1>(&[3]);
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(
+            &mock,
+            Event::Control(Control::Received {
+                size: 1,
+                write: true,
+                address: MatchedAddress::Primary,
+            })
+        );
+    }
+
+    #[test]
+    fn restart_after_nack() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[1, 2]));
+        mock.play_read::<I2C1>();
+        // The master NACKs the first byte and starts the next read right away.
+        mock.replay::<I2C1>(&[Snapshot::nack()]);
+        mock.play_read::<I2C1>();
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(&mock, Event::Notification(Notification::Nacked));
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        // The rest of the first response is gone.
+        assert_next!(
+            &mock,
+            Event::Control(Control::TxEmpty { initial: true, .. })
+        );
+        assert_drained(&mock);
+
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[3]));
+        assert_eq!(mock.master_read::<I2C1>(2), [1, 3]);
+
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn read_quick_command() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[0x80]));
+        mock.play_read::<I2C1>();
+        // The first byte is in DR, but the master stops before clocking it out.
+        mock.replay::<I2C1>(&[Snapshot::stop()]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        // The byte left DR for the shift register, it counts as sent.
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::QuickCommand { write: false })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn stale_stop_with_addr_in_idle() {
+        let mock = MockBridge::<16>::new();
+        mock.replay::<I2C1>(&[Snapshot::addr(false).with_stop(), Snapshot::rxne(1)]);
+
+        // SCL was stretched from the address match, the STOP came before it.
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_drained(&mock);
+        assert!(matches!(I2cBridge::<I2C1>::state(&mock), State::Rx));
+    }
+
+    #[test]
+    fn write_quick_command_without_stretching() {
+        let mock = MockBridge::<16>::new();
+        mock.set_no_stretch(true);
+        mock.replay::<I2C1>(&[Snapshot::addr(false).with_stop()]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(&mock, Event::Control(Control::Received { size: 0, .. }));
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::QuickCommand { write: true })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[test]
+    fn reset_releases_held_address() {
+        let mock = MockBridge::<16>::new().with_backpressure();
+        mock.play_write::<I2C1>(&[1]);
+        while I2cBridge::<I2C1>::try_receive(&mock).is_some() {}
+
+        // The write was never acknowledged with `ack_processed`, the next one is held.
+        mock.replay::<I2C1>(&[Snapshot::addr(false)]);
+        assert_drained(&mock);
+        assert!(mock.is_tx_locked());
+
+        critical_section::with(|cs| I2cBridge::<I2C1>::reset(&mock, cs));
+        assert!(!mock.is_tx_locked());
+
+        mock.play_write::<I2C1>(&[2]);
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: false, .. })
+        );
+        assert_next!(&mock, Event::Control(Control::Received { size: 1, .. }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+    }
+
+    #[cfg(feature = "smbus")]
+    #[test]
+    fn alert_response_keeps_response() {
+        let mock = MockBridge::<16>::new();
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[0xAA, 0xBB]));
+        mock.set_own_address(0x42);
+        mock.set_smbus_alert(true);
+
+        // The host reads the Alert Response Address and gets the own address.
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(1), [0x84]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::AlertResponse));
+        assert_next!(&mock, Event::Notification(Notification::Sent { sent: 1 }));
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(2), [0xAA, 0xBB]);
+    }
+
+    #[test]
+    fn serve_read_counts_every_chunk() {
+        let mock = MockBridge::<4>::new();
+        let slave = I2CSlave::<I2C1, _>::on_bridge(&mock);
+        let data: [u8; 10] = core::array::from_fn(|i| i as u8);
+        let mut serve = pin!(slave.serve_read(|queued| &data[queued..]));
+        let mut read = Vec::<u8, 10>::new();
+
+        mock.play_read::<I2C1>();
+        // The send buffer takes four bytes at a time, each refill starts it over.
+        for _ in 0..3 {
+            assert!(poll_once(serve.as_mut()).is_pending());
+            let chunk = mock.master_read::<I2C1>(data.len() - read.len());
+            read.extend_from_slice(&chunk).unwrap();
+        }
+
+        assert_eq!(read, data);
+        assert!(matches!(poll_once(serve.as_mut()), Poll::Ready(Ok(10))));
+    }
+
+    #[test]
+    fn register_slave_sends_past_send_buffer() {
+        let mock = MockBridge::<4>::new();
+        let registers: [u8; 10] = core::array::from_fn(|i| 10 * i as u8);
+        let mut device = RegisterSlave::new(I2CSlave::<I2C1, _>::on_bridge(&mock), registers);
+        let mut run = pin!(device.run());
+        let mut read = Vec::<u8, 9>::new();
+
+        mock.play_write_read::<I2C1>(&[2]);
+        for _ in 0..3 {
+            assert!(poll_once(run.as_mut()).is_pending());
+            let chunk = mock.master_read::<I2C1>(read.capacity() - read.len());
+            read.extend_from_slice(&chunk).unwrap();
+        }
+
+        assert_eq!(read, [20, 30, 40, 50, 60, 70, 80, 90, 0xFF]);
+    }
+
+    #[test]
+    fn refused_read_gets_fill() {
+        let mock = MockBridge::<16>::new();
+        mock.set_addr_hook(Some(|tx, _| !tx));
+        critical_section::with(|cs| I2cBridge::<I2C1>::write(&mock, cs, &[1]));
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(2), [0xFF, 0xFF]);
+
+        assert_next!(
+            &mock,
+            Event::Notification(Notification::Addr { tx: true, .. })
+        );
+        assert_next!(&mock, Event::Notification(Notification::Stop));
+        assert_drained(&mock);
+
+        // The preloaded response is still there for the next read.
+        mock.set_addr_hook(None);
+        mock.play_read::<I2C1>();
+        assert_eq!(mock.master_read::<I2C1>(1), [1]);
+    }
+}
@@ -1,15 +1,68 @@
-use core::mem::MaybeUninit;
+/// Storage the interrupt handler puts the bytes of a write into.
+///
+/// [`ReceiveBuffer`] is the default, the `R` parameter of [`Bridge`](crate::Bridge) selects
+/// another one. It has to be `Clone` for [`I2CSlave::last_write`](crate::I2CSlave::last_write).
+pub trait RxSink: Clone {
+    /// An empty instance, the bridge needs it in `const` context.
+    const INIT: Self;
 
+    /// The longest write that can be stored.
+    const CAPACITY: usize;
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), ()>;
+
+    /// Returns `true` once when a high-water mark is reached, see
+    /// [`Bridge::with_rx_high_water`](crate::Bridge::with_rx_high_water).
+    fn take_high_water(&mut self) -> bool {
+        false
+    }
+
+    fn get_size(&self) -> usize;
+
+    fn first(&self) -> Option<u8>;
+
+    /// Copies everything into `buf`, or returns the size `buf` has to fit.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, usize>;
+
+    /// Like [`RxSink::read`], but returns the last byte separately.
+    fn read_with_crc(&self, buf: &mut [u8]) -> Result<Option<(usize, u8)>, usize>;
+
+    /// Moves up to `buf.len()` bytes out of the front.
+    fn read_some(&mut self, buf: &mut [u8]) -> usize;
+
+    fn reset(&mut self);
+
+    /// The free space after the stored bytes, a DMA transfer writes into it in place.
+    #[cfg(feature = "dma")]
+    fn spare(&mut self) -> &mut [u8];
+
+    /// Marks the first `n` bytes of [`RxSink::spare`] as stored.
+    #[cfg(feature = "dma")]
+    fn commit(&mut self, n: usize);
+}
+
+#[derive(Clone)]
 pub struct ReceiveBuffer<const BUFSIZE: usize> {
-    buf: MaybeUninit<[u8; BUFSIZE]>,
+    buf: [u8; BUFSIZE],
     size: usize,
+    high_water: Option<usize>,
+    high_water_crossed: bool,
 }
 
 impl<const BUFSIZE: usize> ReceiveBuffer<BUFSIZE> {
     pub const fn new() -> Self {
         Self {
-            buf: MaybeUninit::uninit(),
+            buf: [0; BUFSIZE],
             size: 0,
+            high_water: None,
+            high_water_crossed: false,
+        }
+    }
+
+    pub const fn with_high_water(high_water: usize) -> Self {
+        Self {
+            high_water: Some(high_water),
+            ..Self::new()
         }
     }
 
@@ -17,26 +70,238 @@ impl<const BUFSIZE: usize> ReceiveBuffer<BUFSIZE> {
         if self.size == BUFSIZE {
             Err(())
         } else {
-            unsafe { self.buf.assume_init_mut()[self.size] = byte }
+            self.buf[self.size] = byte;
             self.size += 1;
             Ok(())
         }
     }
 
+    /// Returns `true` once when the buffer fills up to the high-water mark.
+    pub fn take_high_water(&mut self) -> bool {
+        match self.high_water {
+            Some(high_water) if !self.high_water_crossed && self.size >= high_water => {
+                self.high_water_crossed = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
     pub fn get_size(&self) -> usize {
         self.size
     }
 
+    pub fn first(&self) -> Option<u8> {
+        if self.size == 0 {
+            None
+        } else {
+            Some(self.buf[0])
+        }
+    }
+
+    pub fn split_last(&self) -> Option<(&[u8], u8)> {
+        self.buf[..self.size]
+            .split_last()
+            .map(|(&last, rest)| (rest, last))
+    }
+
+    /// Like [`ReceiveBuffer::read`], but without the last byte, which is returned separately.
+    pub fn read_with_crc(&self, buf: &mut [u8]) -> Result<Option<(usize, u8)>, usize> {
+        match self.split_last() {
+            None => Ok(None),
+            Some((payload, _)) if buf.len() < payload.len() => Err(payload.len()),
+            Some((payload, crc)) => {
+                buf[..payload.len()].copy_from_slice(payload);
+                Ok(Some((payload.len(), crc)))
+            }
+        }
+    }
+
     pub fn read(&self, buf: &mut [u8]) -> Result<usize, usize> {
         if buf.len() < self.size {
             Err(self.size)
         } else {
-            buf[..self.size].copy_from_slice(unsafe { &self.buf.assume_init_ref()[..self.size] });
+            buf[..self.size].copy_from_slice(&self.buf[..self.size]);
             Ok(self.size)
         }
     }
 
+    /// Moves up to `buf.len()` bytes out of the front of the buffer.
+    pub fn read_some(&mut self, buf: &mut [u8]) -> usize {
+        let n = core::cmp::min(buf.len(), self.size);
+        buf[..n].copy_from_slice(&self.buf[..n]);
+        self.consume(n);
+        n
+    }
+
+    /// Drops the first `n` bytes, moving the rest to the front.
+    ///
+    /// The high-water mark is armed again once the buffer is below it.
+    pub fn consume(&mut self, n: usize) {
+        let n = core::cmp::min(n, self.size);
+        self.buf.copy_within(n..self.size, 0);
+        self.size -= n;
+
+        if matches!(self.high_water, Some(high_water) if self.size < high_water) {
+            self.high_water_crossed = false;
+        }
+    }
+
     pub fn reset(&mut self) {
         self.size = 0;
+        self.high_water_crossed = false;
+    }
+}
+
+impl<const BUFSIZE: usize> RxSink for ReceiveBuffer<BUFSIZE> {
+    const INIT: Self = Self::new();
+
+    const CAPACITY: usize = BUFSIZE;
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), ()> {
+        ReceiveBuffer::write_byte(self, byte)
+    }
+
+    fn take_high_water(&mut self) -> bool {
+        ReceiveBuffer::take_high_water(self)
+    }
+
+    fn get_size(&self) -> usize {
+        ReceiveBuffer::get_size(self)
+    }
+
+    fn first(&self) -> Option<u8> {
+        ReceiveBuffer::first(self)
+    }
+
+    fn read(&self, buf: &mut [u8]) -> Result<usize, usize> {
+        ReceiveBuffer::read(self, buf)
+    }
+
+    fn read_with_crc(&self, buf: &mut [u8]) -> Result<Option<(usize, u8)>, usize> {
+        ReceiveBuffer::read_with_crc(self, buf)
+    }
+
+    fn read_some(&mut self, buf: &mut [u8]) -> usize {
+        ReceiveBuffer::read_some(self, buf)
+    }
+
+    fn reset(&mut self) {
+        ReceiveBuffer::reset(self)
+    }
+
+    #[cfg(feature = "dma")]
+    fn spare(&mut self) -> &mut [u8] {
+        &mut self.buf[self.size..]
+    }
+
+    #[cfg(feature = "dma")]
+    fn commit(&mut self, n: usize) {
+        self.size = core::cmp::min(self.size + n, BUFSIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled<const N: usize>(data: &[u8]) -> ReceiveBuffer<N> {
+        let mut rb = ReceiveBuffer::new();
+        for &byte in data {
+            rb.write_byte(byte).unwrap();
+        }
+        rb
+    }
+
+    #[test]
+    fn write_byte_until_full() {
+        let mut rb = filled::<4>(&[1, 2, 3, 4]);
+
+        assert_eq!(rb.write_byte(5), Err(()));
+        assert_eq!(rb.get_size(), 4);
+    }
+
+    #[test]
+    fn read_needs_room() {
+        let rb = filled::<4>(&[1, 2, 3]);
+        let mut buf = [0u8; 4];
+
+        assert_eq!(rb.read(&mut buf[..2]), Err(3));
+        assert_eq!(rb.read(&mut buf), Ok(3));
+        assert_eq!(buf[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn read_with_crc_splits_last() {
+        let rb = filled::<4>(&[1, 2, 0xCC]);
+        let mut buf = [0u8; 2];
+
+        assert_eq!(rb.read_with_crc(&mut buf[..1]), Err(2));
+        assert_eq!(rb.read_with_crc(&mut buf), Ok(Some((2, 0xCC))));
+        assert_eq!(buf, [1, 2]);
+        assert_eq!(filled::<4>(&[]).read_with_crc(&mut buf), Ok(None));
+    }
+
+    #[test]
+    fn read_some_moves_rest_to_front() {
+        for len in 0..=4 {
+            let mut rb = filled::<4>(&[1, 2, 3, 4]);
+            let mut buf = [0u8; 4];
+
+            assert_eq!(rb.read_some(&mut buf[..len]), len);
+            assert_eq!(buf[..len], [1, 2, 3, 4][..len]);
+            assert_eq!(rb.get_size(), 4 - len);
+            assert_eq!(rb.first(), [1, 2, 3, 4].get(len).copied());
+        }
+    }
+
+    #[test]
+    fn consume_past_end() {
+        let mut rb = filled::<4>(&[1, 2]);
+
+        rb.consume(3);
+        assert_eq!(rb.get_size(), 0);
+        assert!(rb.write_byte(3).is_ok());
+        assert_eq!(rb.first(), Some(3));
+    }
+
+    #[test]
+    fn high_water_fires_once() {
+        let mut rb = ReceiveBuffer::<4>::with_high_water(2);
+
+        rb.write_byte(1).unwrap();
+        assert!(!rb.take_high_water());
+        rb.write_byte(2).unwrap();
+        assert!(rb.take_high_water());
+        rb.write_byte(3).unwrap();
+        assert!(!rb.take_high_water());
+    }
+
+    #[test]
+    fn high_water_rearmed_below_mark() {
+        let mut rb = ReceiveBuffer::<4>::with_high_water(2);
+        for byte in 1..=3 {
+            rb.write_byte(byte).unwrap();
+        }
+        assert!(rb.take_high_water());
+
+        // Still at the mark.
+        rb.consume(1);
+        assert!(!rb.take_high_water());
+
+        rb.consume(1);
+        rb.write_byte(4).unwrap();
+        assert!(rb.take_high_water());
+    }
+
+    #[test]
+    fn reset_rearms_high_water() {
+        let mut rb = ReceiveBuffer::<4>::with_high_water(1);
+
+        rb.write_byte(1).unwrap();
+        assert!(rb.take_high_water());
+        rb.reset();
+        rb.write_byte(2).unwrap();
+        assert!(rb.take_high_water());
     }
 }
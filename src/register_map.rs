@@ -0,0 +1,62 @@
+use core::cell::RefCell;
+
+use critical_section::{CriticalSection, Mutex};
+
+/// Source of the response data for register-addressed reads.
+///
+/// When attached to a [`Bridge`](crate::Bridge), the first byte of every write is taken as
+/// a register index and reads are served from that index by the interrupt handler.
+pub trait RegisterSource: Sync {
+    /// Copies registers starting at `index` into `buf`, returns the number of bytes copied.
+    fn read_registers(&self, cs: CriticalSection, index: usize, buf: &mut [u8]) -> usize;
+}
+
+/// What a read served from a [`RegisterSource`] does past the last register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RegisterRollover {
+    /// Emits `TxEmpty`, the consumer provides the rest.
+    Stop,
+    /// Goes on from register 0.
+    Wrap,
+    /// Repeats the last register.
+    Clamp,
+}
+
+pub struct RegisterMap<const N: usize> {
+    registers: Mutex<RefCell<[u8; N]>>,
+}
+
+impl<const N: usize> RegisterMap<N> {
+    pub const fn new(registers: [u8; N]) -> Self {
+        Self {
+            registers: Mutex::new(RefCell::new(registers)),
+        }
+    }
+
+    pub fn get(&self, cs: CriticalSection, index: usize) -> u8 {
+        self.registers.borrow_ref(cs)[index]
+    }
+
+    pub fn set(&self, cs: CriticalSection, index: usize, value: u8) {
+        self.registers.borrow_ref_mut(cs)[index] = value;
+    }
+
+    /// Writes `data` starting at `index`, bytes past the last register are dropped.
+    pub fn write(&self, cs: CriticalSection, index: usize, data: &[u8]) {
+        let mut registers = self.registers.borrow_ref_mut(cs);
+        let dst = registers.get_mut(index..).unwrap_or_default();
+        let n = core::cmp::min(dst.len(), data.len());
+        dst[..n].copy_from_slice(&data[..n]);
+    }
+}
+
+impl<const N: usize> RegisterSource for RegisterMap<N> {
+    fn read_registers(&self, cs: CriticalSection, index: usize, buf: &mut [u8]) -> usize {
+        let registers = self.registers.borrow_ref(cs);
+        let src = registers.get(index..).unwrap_or_default();
+        let n = core::cmp::min(src.len(), buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        n
+    }
+}
@@ -0,0 +1,163 @@
+use core::ops::Range;
+
+use embassy_stm32::i2c;
+
+use super::{slave::I2cBridge, Control, Error, Event, I2CSlave};
+
+/// Most read hooks and most write hooks a [`RegisterSlave`] takes.
+pub const MAX_REGISTER_HOOKS: usize = 8;
+
+type WriteHook<const REGS: usize> = fn(&mut [u8; REGS], usize, usize);
+
+type ReadHook<const REGS: usize> = fn(&mut [u8; REGS], usize);
+
+#[derive(Clone, Copy)]
+struct RangeHook<F> {
+    start: usize,
+    end: usize,
+    hook: F,
+}
+
+impl<F: Copy> RangeHook<F> {
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        start < end && self.start < end && start < self.end
+    }
+}
+
+fn add_hook<F: Copy>(hooks: &mut [Option<RangeHook<F>>], range: Range<usize>, hook: F) {
+    let slot = hooks
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("Too many register hooks");
+
+    *slot = Some(RangeHook {
+        start: range.start,
+        end: range.end,
+        hook,
+    });
+}
+
+/// Register-addressed device on top of [`I2CSlave`].
+///
+/// The first byte of every write selects the register index, the rest of the write is stored
+/// from that index on and a following read returns the registers from that index on. Indexes
+/// wrap around at `REGS`, reads past the last register return `0xFF`.
+pub struct RegisterSlave<'d, T: i2c::Instance, B: I2cBridge<T>, const REGS: usize> {
+    slave: I2CSlave<'d, T, B>,
+    registers: [u8; REGS],
+    index: usize,
+    /// The next register to queue for the current read.
+    pos: usize,
+    write_hooks: [Option<RangeHook<WriteHook<REGS>>>; MAX_REGISTER_HOOKS],
+    read_hooks: [Option<RangeHook<ReadHook<REGS>>>; MAX_REGISTER_HOOKS],
+}
+
+impl<'d, T: i2c::Instance, B: I2cBridge<T>, const REGS: usize> RegisterSlave<'d, T, B, REGS> {
+    const NONZERO_REGS: () = assert!(REGS > 0, "RegisterSlave needs at least one register");
+
+    pub fn new(slave: I2CSlave<'d, T, B>, registers: [u8; REGS]) -> Self {
+        let () = Self::NONZERO_REGS;
+
+        Self {
+            slave,
+            registers,
+            index: 0,
+            pos: 0,
+            write_hooks: [None; MAX_REGISTER_HOOKS],
+            read_hooks: [None; MAX_REGISTER_HOOKS],
+        }
+    }
+
+    /// Adds a hook for the registers in `range`, called with the registers, the index and the
+    /// length of every write that stored data into the range, after the data has been stored.
+    ///
+    /// Panics if [`MAX_REGISTER_HOOKS`] write hooks are set already.
+    pub fn with_write_hook(mut self, range: Range<usize>, hook: WriteHook<REGS>) -> Self {
+        add_hook(&mut self.write_hooks, range, hook);
+        self
+    }
+
+    /// Adds a hook for the registers in `range`, called with the registers and the index at
+    /// the start of every read that can reach the range, to update the registers about to be
+    /// sent.
+    ///
+    /// Panics if [`MAX_REGISTER_HOOKS`] read hooks are set already.
+    pub fn with_read_hook(mut self, range: Range<usize>, hook: ReadHook<REGS>) -> Self {
+        add_hook(&mut self.read_hooks, range, hook);
+        self
+    }
+
+    pub fn slave(&self) -> &I2CSlave<'d, T, B> {
+        &self.slave
+    }
+
+    pub fn registers(&self) -> &[u8; REGS] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8; REGS] {
+        &mut self.registers
+    }
+
+    /// Serves the master until the slave fails, other events are ignored.
+    pub async fn run(&mut self) -> Error {
+        loop {
+            match self.slave.listen().await {
+                Ok(Event::Control(Control::Received { .. })) => {
+                    self.store_write();
+                    self.slave.ack_processed();
+                }
+                Ok(Event::Control(Control::TxEmpty { initial, .. })) => self.respond(initial),
+                Ok(_) => {}
+                Err(err) => return err,
+            }
+        }
+    }
+
+    fn respond(&mut self, initial: bool) {
+        if initial {
+            for hook in self.read_hooks.iter().flatten() {
+                if hook.overlaps(self.index, REGS) {
+                    (hook.hook)(&mut self.registers, self.index);
+                }
+            }
+
+            self.pos = self.index;
+        }
+
+        // What did not fit into the send buffer goes out on the next `TxEmpty`.
+        match &self.registers[self.pos..] {
+            [] => {
+                self.slave.write(&[0xFF]);
+            }
+            tail => self.pos += tail.len() - self.slave.write(tail).len(),
+        }
+    }
+
+    fn store_write(&mut self) {
+        let (index, len) = critical_section::with(|cs| {
+            let mut index = [0u8];
+            if self.slave.read_some(cs, &mut index) == 0 {
+                return (None, 0);
+            }
+
+            let index = index[0] as usize % REGS;
+            let len = self.slave.read_some(cs, &mut self.registers[index..]);
+
+            // Drop whatever ran past the last register.
+            while self.slave.read_some(cs, &mut [0u8; 8]) != 0 {}
+
+            (Some(index), len)
+        });
+
+        if let Some(index) = index {
+            self.index = index;
+
+            for hook in self.write_hooks.iter().flatten() {
+                if hook.overlaps(index, index + len) {
+                    (hook.hook)(&mut self.registers, index, len);
+                }
+            }
+        }
+    }
+}
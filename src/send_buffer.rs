@@ -1,7 +1,42 @@
-use core::{cmp, mem::MaybeUninit};
+use core::cmp;
+
+/// Storage the interrupt handler takes the bytes of a read from.
+///
+/// [`SendBuffer`] is the default, the `S` parameter of [`Bridge`](crate::Bridge) selects
+/// another one.
+pub trait TxSource {
+    /// An empty instance, the bridge needs it in `const` context.
+    const INIT: Self;
+
+    /// The most [`TxSource::write`] can take while empty.
+    const CAPACITY: usize;
+
+    fn next_byte(&mut self) -> Option<u8>;
+
+    fn is_empty(&self) -> bool;
+
+    /// Number of bytes not handed out yet.
+    fn remaining(&self) -> usize;
+
+    /// Appends as much of `buf` as fits, returns the rest.
+    fn write<'a>(&mut self, buf: &'a [u8]) -> &'a [u8];
+
+    /// Lets `f` fill the empty storage directly, `f` returns the number of bytes written.
+    fn fill_with(&mut self, f: impl FnOnce(&mut [u8]) -> usize) -> usize;
+
+    fn reset(&mut self);
+
+    /// The bytes not handed out yet, a DMA transfer reads them in place.
+    #[cfg(feature = "dma")]
+    fn pending(&self) -> &[u8];
+
+    /// Marks the first `n` pending bytes as handed out.
+    #[cfg(feature = "dma")]
+    fn advance(&mut self, n: usize);
+}
 
 pub struct SendBuffer<const BUFSIZE: usize> {
-    buf: MaybeUninit<[u8; BUFSIZE]>,
+    buf: [u8; BUFSIZE],
     pos: usize,
     end: usize,
 }
@@ -9,29 +44,40 @@ pub struct SendBuffer<const BUFSIZE: usize> {
 impl<const BUFSIZE: usize> SendBuffer<BUFSIZE> {
     pub const fn new() -> Self {
         Self {
-            buf: MaybeUninit::uninit(),
+            buf: [0; BUFSIZE],
             pos: 0,
             end: 0,
         }
     }
 
+    /// Appends as much of `buf` as fits after the pending data, returns the rest.
+    ///
+    /// Once everything written so far has been sent, the buffer starts over from the beginning.
     pub fn write<'a>(&mut self, buf: &'a [u8]) -> &'a [u8] {
-        assert!(
-            buf.len() <= BUFSIZE,
-            "Trying to write too much data into the send buffer"
-        );
+        if self.is_empty() {
+            self.pos = 0;
+            self.end = 0;
+        }
+
+        let take_idx = cmp::min(buf.len(), BUFSIZE - self.end);
+        self.buf[self.end..self.end + take_idx].copy_from_slice(&buf[..take_idx]);
+
+        self.end += take_idx;
 
+        &buf[take_idx..]
+    }
+
+    pub fn fill_with(&mut self, f: impl FnOnce(&mut [u8]) -> usize) -> usize {
         if !self.is_empty() {
             panic!("Send buffer must be reset before writing.");
         }
 
-        let take_idx = cmp::min(buf.len(), BUFSIZE);
-        unsafe { self.buf.assume_init_mut()[..take_idx].copy_from_slice(buf) };
+        let n = cmp::min(f(&mut self.buf), BUFSIZE);
 
         self.pos = 0;
-        self.end = take_idx;
+        self.end = n;
 
-        &buf[take_idx..]
+        n
     }
 
     pub fn reset(&mut self) {
@@ -46,6 +92,50 @@ impl<const BUFSIZE: usize> SendBuffer<BUFSIZE> {
     pub fn is_empty(&self) -> bool {
         self.end == self.pos
     }
+
+    pub fn remaining(&self) -> usize {
+        self.end - self.pos
+    }
+}
+
+impl<const BUFSIZE: usize> TxSource for SendBuffer<BUFSIZE> {
+    const INIT: Self = Self::new();
+
+    const CAPACITY: usize = BUFSIZE;
+
+    fn next_byte(&mut self) -> Option<u8> {
+        self.next()
+    }
+
+    fn is_empty(&self) -> bool {
+        SendBuffer::is_empty(self)
+    }
+
+    fn remaining(&self) -> usize {
+        SendBuffer::remaining(self)
+    }
+
+    fn write<'a>(&mut self, buf: &'a [u8]) -> &'a [u8] {
+        SendBuffer::write(self, buf)
+    }
+
+    fn fill_with(&mut self, f: impl FnOnce(&mut [u8]) -> usize) -> usize {
+        SendBuffer::fill_with(self, f)
+    }
+
+    fn reset(&mut self) {
+        SendBuffer::reset(self)
+    }
+
+    #[cfg(feature = "dma")]
+    fn pending(&self) -> &[u8] {
+        &self.buf[self.pos..self.end]
+    }
+
+    #[cfg(feature = "dma")]
+    fn advance(&mut self, n: usize) {
+        self.pos = cmp::min(self.pos + n, self.end);
+    }
 }
 
 impl<const BUFSIZE: usize> Iterator for SendBuffer<BUFSIZE> {
@@ -56,7 +146,73 @@ impl<const BUFSIZE: usize> Iterator for SendBuffer<BUFSIZE> {
             None
         } else {
             self.pos += 1;
-            Some(unsafe { self.buf.assume_init_ref()[self.pos - 1] })
+            Some(self.buf[self.pos - 1])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_returns_tail() {
+        for len in 0..=8 {
+            let data: [u8; 8] = core::array::from_fn(|i| i as u8);
+            let mut sb = SendBuffer::<4>::new();
+
+            let tail = sb.write(&data[..len]);
+            let taken = cmp::min(len, 4);
+            assert_eq!(tail, &data[taken..len]);
+            assert_eq!(sb.remaining(), taken);
+
+            for &byte in &data[..taken] {
+                assert_eq!(sb.next(), Some(byte));
+            }
+            assert_eq!(sb.next(), None);
+            assert_eq!(sb.bytes_sent(), taken);
         }
     }
+
+    #[test]
+    fn write_appends() {
+        let mut sb = SendBuffer::<4>::new();
+
+        assert!(sb.write(&[1, 2]).is_empty());
+        assert_eq!(sb.next(), Some(1));
+        assert_eq!(sb.write(&[3, 4, 5]), &[5]);
+        assert!(sb.by_ref().eq([2, 3, 4]));
+        assert_eq!(sb.next(), None);
+    }
+
+    #[test]
+    fn write_starts_over_once_sent() {
+        let mut sb = SendBuffer::<4>::new();
+
+        assert!(sb.write(&[1, 2, 3, 4]).is_empty());
+        assert_eq!(sb.by_ref().count(), 4);
+        assert!(sb.write(&[5, 6, 7, 8]).is_empty());
+        assert!(sb.by_ref().eq([5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn reset_drops_pending() {
+        let mut sb = SendBuffer::<4>::new();
+
+        sb.write(&[1, 2, 3]);
+        sb.next();
+        sb.reset();
+
+        assert!(sb.is_empty());
+        assert_eq!(sb.bytes_sent(), 0);
+        assert_eq!(sb.next(), None);
+    }
+
+    #[test]
+    fn fill_with_is_capped() {
+        let mut sb = SendBuffer::<4>::new();
+
+        assert_eq!(sb.fill_with(|buf| buf.len() + 1), 4);
+        assert_eq!(sb.remaining(), 4);
+    }
 }
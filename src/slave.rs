@@ -1,66 +1,297 @@
-use core::marker::PhantomData;
+#[cfg(feature = "time")]
+use core::time::Duration;
+use core::{
+    future::Future,
+    marker::PhantomData,
+    task::{Context, Poll},
+};
 
 use critical_section::CriticalSection;
 use embassy_stm32::{
-    gpio::{low_level::AFType, Pull},
+    gpio::low_level::AFType,
     i2c::{self, SclPin, SdaPin},
+    interrupt::typelevel::Interrupt,
     pac,
     time::Hertz,
     Peripheral,
 };
 
+use crate::{Config, ConfigError, HwStatus, MatchedAddress, SendError, State};
 pub use crate::{Control, Error, Event, Notification};
 
 pub trait I2cBridge<T: i2c::Instance> {
-    async fn receive(&self) -> Result<Event, Error>;
+    const TX_CAPACITY: usize;
+
+    const RX_CAPACITY: usize;
+
+    fn receive(&self) -> impl Future<Output = Result<Event, Error>>;
+
+    fn try_receive(&self) -> Option<Result<Event, Error>>;
+
+    fn poll_receive(&self, cx: &mut Context<'_>) -> Poll<Result<Event, Error>>;
+
+    fn state(&self) -> State;
 
     fn write<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8];
 
+    fn clear_tx(&self, cs: CriticalSection);
+
+    fn stage_tx<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8];
+
+    fn tx_remaining(&self, cs: CriticalSection) -> usize;
+
     fn read(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize>;
+
+    fn read_some(&self, cs: CriticalSection, buf: &mut [u8]) -> usize;
+
+    fn read_with_crc(
+        &self,
+        cs: CriticalSection,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, u8)>, usize>;
+
+    fn last_write(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize>;
+
+    #[cfg(feature = "frames")]
+    fn read_frame(&self, cs: CriticalSection, buf: &mut [u8]) -> Option<Result<usize, usize>>;
+
+    fn last_pec(&self) -> u8;
+
+    fn last_error(&self) -> Option<Error>;
+
+    fn matched_address(&self) -> MatchedAddress;
+
+    fn ack_processed(&self);
+
+    fn set_disable_on_error(&self, disable: bool);
+
+    fn set_responsive(&self, responsive: bool);
+
+    fn is_responsive(&self) -> bool;
+
+    #[cfg(feature = "dma")]
+    fn set_tx_dma(&self, enable: bool);
+
+    #[cfg(feature = "dma")]
+    fn set_rx_dma(&self, enable: bool);
+
+    #[cfg(feature = "async-write")]
+    fn wait_txbuf_drained(&self) -> impl Future<Output = ()>;
+
+    /// Forgets a drain no one has waited for yet.
+    #[cfg(feature = "async-write")]
+    fn reset_txbuf_drained(&self);
+
+    fn reset(&self, cs: CriticalSection);
+
+    #[cfg(feature = "history")]
+    fn clear_history(&self, cs: CriticalSection);
+}
+
+/// CCR value for a Standard-mode SCL of `speed` with a peripheral clock of `pclk`.
+///
+/// The division rounds down, see [`I2CSlave::actual_speed`] for the resulting rate.
+pub const fn compute_ccr(pclk: Hertz, speed: Hertz) -> u16 {
+    (pclk.0 / speed.0 / 2) as u16
+}
+
+#[cfg(feature = "dma")]
+fn enable_dma_clock() {
+    pac::RCC.ahbenr().modify(|w| w.set_dma1en(true));
+}
+
+/// The own address in `oar1`. In 7-bit mode ADD holds it shifted left by one, as sent on the bus.
+fn own_address(oar1: pac::i2c::regs::Oar1) -> u16 {
+    if oar1.addmode() == pac::i2c::vals::Addmode::BIT10 {
+        oar1.add() & 0x3FF
+    } else {
+        (oar1.add() >> 1) & 0x7F
+    }
+}
+
+fn trise(freq_mhz: u8, rise_time_ns: Option<u32>) -> u64 {
+    rise_time_ns.unwrap_or(1000) as u64 * freq_mhz as u64 / 1000 + 1
 }
 
 pub struct I2CSlave<'d, T: i2c::Instance, B: I2cBridge<T>> {
     bridge: &'d B,
+    speed: Hertz,
+    own_address: u16,
+    config: Config,
     _marker: PhantomData<T>,
 }
 
 impl<'d, T: i2c::Instance, B: I2cBridge<T>> I2CSlave<'d, T, B> {
+    /// Panicking version of [`I2CSlave::try_new`].
     pub fn new(
-        _i2c: impl Peripheral<P = T> + 'd,
+        i2c: impl Peripheral<P = T> + 'd,
         bridge: &'d B,
         scl: impl Peripheral<P = impl SclPin<T>> + 'd,
         sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
         speed: Hertz,
-        own_address: u8,
+        own_address: u16,
+        config: Config,
     ) -> Self {
-        assert!(speed <= Hertz(100_000), "Fast-mode is not supported");
-        assert!(
-            own_address <= 127,
-            "Own address is out of range. 10-bit addresses are not supported."
-        );
+        Self::try_new(i2c, bridge, scl, sda, speed, own_address, config)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Sets up the peripheral and the pins as a slave listening on `own_address`, 7-bit unless
+    /// [`Config::ten_bit_address`] is set.
+    ///
+    /// The arguments are checked before anything is touched, on error the peripheral is left
+    /// as it was.
+    pub fn try_new(
+        _i2c: impl Peripheral<P = T> + 'd,
+        bridge: &'d B,
+        scl: impl Peripheral<P = impl SclPin<T>> + 'd,
+        sda: impl Peripheral<P = impl SdaPin<T>> + 'd,
+        speed: Hertz,
+        own_address: u16,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        if speed.0 == 0 || speed > Hertz(100_000) {
+            return Err(ConfigError::SpeedNotSupported);
+        }
+        let max_address = if config.ten_bit_address { 0x3FF } else { 0x7F };
+        if own_address > max_address || config.own_address2.is_some_and(|addr| addr > 127) {
+            return Err(ConfigError::AddressOutOfRange);
+        }
+        if !config.analog_filter || config.digital_filter != 0 {
+            return Err(ConfigError::FilterNotSupported);
+        }
+        if T::frequency().0 < 2_000_000 {
+            return Err(ConfigError::ClockTooSlow);
+        }
+        if trise((T::frequency().0 / 1_000_000) as u8, config.rise_time_ns) > 63 {
+            return Err(ConfigError::RiseTimeTooLong);
+        }
 
         T::enable_and_reset();
 
         let scl = scl.into_ref();
         let sda = sda.into_ref();
 
-        scl.set_as_af_pull(scl.af_num(), AFType::OutputOpenDrain, Pull::None);
-        sda.set_as_af_pull(sda.af_num(), AFType::OutputOpenDrain, Pull::None);
+        scl.set_as_af_pull(scl.af_num(), AFType::OutputOpenDrain, config.pull);
+        sda.set_as_af_pull(sda.af_num(), AFType::OutputOpenDrain, config.pull);
+
+        debug!(
+            "I2C slave at {:#x}: SCL AF {}, SDA AF {}, address {:#x}",
+            T::regs().as_ptr() as u32,
+            scl.af_num(),
+            sda.af_num(),
+            own_address
+        );
+
+        bridge.set_disable_on_error(config.disable_on_error);
+
+        let slave = Self {
+            bridge,
+            speed,
+            own_address,
+            config,
+            _marker: PhantomData,
+        };
+        slave.configure();
+        Ok(slave)
+    }
+
+    /// Takes over a peripheral and pins set up elsewhere, already enabled as a slave with its
+    /// interrupts armed. Nothing is written to the peripheral.
+    ///
+    /// The own addresses, the speed and the PEC and clock stretching settings are read back
+    /// from the registers, for [`I2CSlave::hard_reset`] to restore them.
+    pub fn from_configured(_i2c: impl Peripheral<P = T> + 'd, bridge: &'d B) -> Self {
+        let regs = T::regs();
+        let cr1 = regs.cr1().read();
+        let oar1 = regs.oar1().read();
+        let oar2 = regs.oar2().read();
+
+        debug_assert!(!cr1.pos(), "CR1.POS must be clear in slave mode");
+        let ccr = regs.ccr().read().ccr() as u32;
+
+        Self {
+            bridge,
+            speed: Hertz(T::frequency().0 / (2 * ccr.max(1))),
+            own_address: own_address(oar1),
+            config: Config {
+                pec: cr1.enpec(),
+                no_stretch: cr1.nostretch(),
+                ten_bit_address: oar1.addmode() == pac::i2c::vals::Addmode::BIT10,
+                own_address2: (oar2.endual() == pac::i2c::vals::Endual::DUAL).then(|| oar2.add2()),
+                ..Config::default()
+            },
+            _marker: PhantomData,
+        }
+    }
 
+    /// A slave on `bridge` that never touches the peripheral, for the mock tests.
+    #[cfg(all(test, feature = "testing"))]
+    pub(crate) fn on_bridge(bridge: &'d B) -> Self {
+        Self {
+            bridge,
+            speed: Hertz(100_000),
+            own_address: 0,
+            config: Config::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Streams reads out of the send buffer with DMA, on the DMA1 channel wired to the TX
+    /// request of `T`: channel 6 for I2C1, 4 for I2C2. The channel is given up to the slave,
+    /// nothing else may use it.
+    ///
+    /// The transfer starts on the first TXE of a read, bytes written after that go out from
+    /// the interrupt handler as usual. [`handle_dma_interrupt`](crate::handle_dma_interrupt)
+    /// has to be called from the channel interrupt.
+    #[cfg(feature = "dma")]
+    pub fn with_tx_dma(self, _dma: impl Peripheral<P = impl i2c::TxDma<T>> + 'd) -> Self {
+        enable_dma_clock();
+        self.bridge.set_tx_dma(true);
+        self
+    }
+
+    /// Streams writes into the receive buffer with DMA, on the DMA1 channel wired to the RX
+    /// request of `T`: channel 7 for I2C1, 5 for I2C2. The channel is given up to the slave,
+    /// nothing else may use it.
+    ///
+    /// `Received` comes at the end of the write as usual, or early once the transfer has filled
+    /// the receive buffer. The receive buffer must not be drained while the write goes on, the
+    /// transfer writes into it in place.
+    #[cfg(feature = "dma")]
+    pub fn with_rx_dma(self, _dma: impl Peripheral<P = impl i2c::RxDma<T>> + 'd) -> Self {
+        enable_dma_clock();
+        self.bridge.set_rx_dma(true);
+        self
+    }
+
+    fn configure(&self) {
         let clock_frequency = T::frequency();
         let freq = (clock_frequency.0 / 1_000_000) as u8;
-        assert!(
-            freq >= 2,
-            "Bus frequency in Standard Mode must be at least 2MHz"
-        );
 
         let regs = T::regs();
 
         regs.cr1().modify(|w| w.set_pe(false));
 
+        #[cfg(feature = "dma")]
+        enable_dma_clock();
+
         regs.oar1().modify(|w| {
-            w.set_addmode(pac::i2c::vals::Addmode::BIT7);
-            w.set_add((own_address << 1) as u16);
+            if self.config.ten_bit_address {
+                w.set_addmode(pac::i2c::vals::Addmode::BIT10);
+                w.set_add(self.own_address);
+            } else {
+                w.set_addmode(pac::i2c::vals::Addmode::BIT7);
+                w.set_add(self.own_address << 1);
+            }
+        });
+
+        regs.oar2().modify(|w| match self.config.own_address2 {
+            Some(addr) => {
+                w.set_endual(pac::i2c::vals::Endual::DUAL);
+                w.set_add2(addr);
+            }
+            None => w.set_endual(pac::i2c::vals::Endual::SINGLE),
         });
 
         regs.cr2().modify(|w| {
@@ -70,42 +301,620 @@ impl<'d, T: i2c::Instance, B: I2cBridge<T>> I2CSlave<'d, T, B> {
             w.set_freq(freq);
         });
 
-        regs.trise().modify(|w| w.set_trise(freq + 1));
+        regs.trise()
+            .modify(|w| w.set_trise(trise(freq, self.config.rise_time_ns) as u8));
 
         regs.ccr().modify(|w| {
-            w.set_ccr((clock_frequency.0 / speed.0 / 2) as u16);
+            w.set_ccr(compute_ccr(clock_frequency, self.speed));
             w.set_duty(pac::i2c::vals::Duty::DUTY2_1);
             w.set_f_s(pac::i2c::vals::FS::STANDARD);
         });
+        #[cfg(feature = "smbus")]
+        regs.cr1().modify(|w| {
+            w.set_smbus(true);
+            w.set_smbtype(pac::i2c::vals::Smbtype::DEVICE);
+        });
+
         regs.cr1().modify(|w| {
+            w.set_enpec(self.config.pec);
+            w.set_nostretch(self.config.no_stretch);
             w.set_engc(true);
-            w.set_ack(true);
+            w.set_ack(self.bridge.is_responsive());
+            // POS only matters to a master receiving two bytes, left set by an earlier driver
+            // it would move the ACK of every received byte to the next one.
+            w.set_pos(false);
             w.set_pe(true);
         });
+    }
 
-        Self {
-            bridge,
-            _marker: PhantomData,
+    /// Size of the send buffer, the most a single [`I2CSlave::write`] can queue.
+    pub const fn tx_capacity() -> usize {
+        B::TX_CAPACITY
+    }
+
+    /// Size of the receive buffer, the longest write that is received in one go.
+    pub const fn rx_capacity() -> usize {
+        B::RX_CAPACITY
+    }
+
+    /// Unmasks the event and error interrupts of the instance.
+    ///
+    /// The handlers share the bridge and must not preempt each other, so if the priorities
+    /// are changed with `T::EventInterrupt::set_priority` and `T::ErrorInterrupt::set_priority`,
+    /// give both lines the same one, before calling this.
+    ///
+    /// # Safety
+    ///
+    /// Same as for unmasking any interrupt: it must not break a critical section relying on
+    /// these interrupts being masked.
+    pub unsafe fn enable_interrupts(&self) {
+        T::EventInterrupt::enable();
+        T::ErrorInterrupt::enable();
+    }
+
+    /// Masks the event and error interrupts of the instance, no events are produced until
+    /// [`I2CSlave::enable_interrupts`] is called again.
+    pub fn disable_interrupts(&self) {
+        T::EventInterrupt::disable();
+        T::ErrorInterrupt::disable();
+    }
+
+    /// Returns `true` if the last address match was a general call, as in `Notification::Addr`.
+    pub fn last_addr_was_gencall(&self) -> bool {
+        matches!(self.matched_address(), MatchedAddress::GeneralCall)
+    }
+
+    /// Address of the last address match, also carried by `Received` and `TxEmpty`.
+    pub fn matched_address(&self) -> MatchedAddress {
+        self.bridge.matched_address()
+    }
+
+    /// Own address read back from OAR1, 10-bit with [`Config::ten_bit_address`].
+    pub fn own_address(&self) -> u16 {
+        own_address(T::regs().oar1().read())
+    }
+
+    /// Second 7-bit address read back from OAR2, `None` unless dual-address mode is enabled.
+    pub fn own_address2(&self) -> Option<u8> {
+        let oar2 = T::regs().oar2().read();
+        (oar2.endual() == pac::i2c::vals::Endual::DUAL).then(|| oar2.add2())
+    }
+
+    /// Address of the last address match, 0 for a general call.
+    pub fn matched_address_value(&self) -> u16 {
+        match self.matched_address() {
+            MatchedAddress::Primary => self.own_address,
+            MatchedAddress::Secondary => {
+                self.config.own_address2.map_or(self.own_address, u16::from)
+            }
+            MatchedAddress::GeneralCall => 0,
         }
     }
 
+    /// Tells the bridge the last received write has been processed.
+    ///
+    /// Only needed with [`Bridge::with_backpressure`](crate::Bridge::with_backpressure): a
+    /// master addressing the slave in the meantime is held by clock stretching until then.
+    pub fn ack_processed(&self) {
+        self.bridge.ack_processed()
+    }
+
+    /// Sets whether the slave answers its addresses, without reconfiguring the peripheral.
+    ///
+    /// While not responsive the own addresses and the general call are NACKed, so the master
+    /// sees no device. A transaction under way is finished first, the setting takes effect at
+    /// its STOP. It outlasts [`I2CSlave::rearm`] and [`I2CSlave::hard_reset`].
+    pub fn set_responsive(&self, responsive: bool) {
+        self.bridge.set_responsive(responsive)
+    }
+
+    pub fn is_responsive(&self) -> bool {
+        self.bridge.is_responsive()
+    }
+
+    /// PEC computed by the hardware over the last transaction, captured at its STOP.
+    ///
+    /// Requires [`Config::pec`], the value stays `0` otherwise.
+    pub fn last_pec(&self) -> u8 {
+        self.bridge.last_pec()
+    }
+
+    /// Raw access to the peripheral registers.
+    ///
+    /// # Safety
+    ///
+    /// The interrupt handlers access the same registers and track the transfer state in
+    /// software. Changing anything they depend on (interrupt enables, ACK, PE) or clearing
+    /// status flags can desynchronize the state machine or lose events.
+    pub unsafe fn regs(&self) -> pac::i2c::I2c {
+        T::regs()
+    }
+
+    /// SCL rate programmed into CCR, without the rise and fall times.
+    ///
+    /// In slave mode the master drives SCL, the value only matters if the peripheral is later
+    /// used as a master.
+    pub fn actual_speed(&self) -> Hertz {
+        // CCR is 0 until the peripheral has been configured.
+        let ccr = T::regs().ccr().read().ccr().max(1) as u32;
+        Hertz(T::frequency().0 / (2 * ccr))
+    }
+
+    /// Number of bytes in the send buffer the master has not read yet.
+    ///
+    /// With `dma` the bytes of a running transfer count until it ends.
+    pub fn tx_remaining(&self, cs: CriticalSection) -> usize {
+        self.bridge.tx_remaining(cs)
+    }
+
+    /// Rough time until the send buffer runs empty during a read, 9 SCL cycles per byte at
+    /// the configured speed.
+    ///
+    /// The master drives SCL, so it is only as good as the configured speed matches the bus,
+    /// and pauses of the master between bytes are not included.
+    #[cfg(feature = "time")]
+    pub fn tx_time_remaining(&self, cs: CriticalSection) -> Duration {
+        let bits = self.tx_remaining(cs) as u64 * 9;
+        Duration::from_nanos(bits * 1_000_000_000 / self.speed.0 as u64)
+    }
+
+    /// Queues `buf` for the master to read, returns the part that did not fit.
+    ///
+    /// The data is appended to what is pending. Outside of a read it preloads the response of
+    /// the next read, so its first byte goes out without a `TxEmpty`. Several writes build the
+    /// response up piece by piece, [`I2CSlave::clear_tx`] drops a stale one.
+    ///
+    /// During a read the data goes out in the same read while the send buffer still has data
+    /// or the interrupt handler has asked for more with a `TxEmpty` or `TxByte`. Once the
+    /// buffer has run out without such a request the master may be ending the read, so the
+    /// data is queued after it, as with [`I2CSlave::stage_tx`]. With a fill byte set in
+    /// [`Bridge::with_underrun`](crate::Bridge::with_underrun) a read is therefore not
+    /// extended once its data has run out. A write answering a `TxEmpty` after the read has
+    /// ended preloads the next one.
+    ///
+    /// The returned slice is the tail of `buf` that did not fit, empty if all of it was
+    /// queued. It can be written again once the master has read some of the pending data,
+    /// [`I2CSlave::write_all`] is simpler when the response always has to fit.
     pub fn write_cs<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
         self.bridge.write(cs, buf)
     }
 
+    /// Drops the data preloaded for the next read.
+    ///
+    /// During a read nothing is dropped, the interrupt handler may be sending the pending data
+    /// at that moment.
+    pub fn clear_tx(&self, cs: CriticalSection) {
+        self.bridge.clear_tx(cs)
+    }
+
+    /// [`I2CSlave::write_cs`] in its own critical section.
     pub fn write<'a>(&self, buf: &'a [u8]) -> &'a [u8] {
         critical_section::with(|cs| self.write_cs(cs, buf))
     }
 
+    /// Like [`I2CSlave::write`], but fails if `buf` did not fit as a whole. The part that did
+    /// fit stays queued.
+    pub fn write_all(&self, buf: &[u8]) -> Result<(), SendError> {
+        match self.write(buf).len() {
+            0 => Ok(()),
+            rest => Err(SendError {
+                written: buf.len() - rest,
+            }),
+        }
+    }
+
+    /// Prepares the response to the next read while the current one may still be sent.
+    ///
+    /// `buf` goes into a second send buffer, which replaces the active one at the start of the
+    /// next read, whatever is left in the active one is dropped. Like [`I2CSlave::write_cs`]
+    /// it returns the part of `buf` that did not fit. The second buffer costs another
+    /// `TXBUFSIZE` bytes of RAM.
+    pub fn stage_tx<'a>(&self, cs: CriticalSection, buf: &'a [u8]) -> &'a [u8] {
+        self.bridge.stage_tx(cs, buf)
+    }
+
+    /// Writes all of `buf`, waiting for the master to clock out the pending data whenever the
+    /// send buffer is full.
+    ///
+    /// The buffer makes room again once the interrupt handler asks for more data or the read
+    /// ended.
+    #[cfg(feature = "async-write")]
+    pub async fn write_async(&self, mut buf: &[u8]) {
+        loop {
+            // A drain signalled before this write, like at the end of the last read, says
+            // nothing about the room left after it.
+            self.bridge.reset_txbuf_drained();
+            buf = self.write(buf);
+
+            if buf.is_empty() {
+                return;
+            }
+
+            self.bridge.wait_txbuf_drained().await;
+        }
+    }
+
+    /// Waits until the interrupt handler has sent everything in the send buffer and asks for
+    /// more, or the read ended, to push the next chunk of a long response.
+    ///
+    /// A drain that happened before the call and was not waited for completes it right away.
+    #[cfg(feature = "async-write")]
+    pub async fn wait_tx_drained(&self) {
+        self.bridge.wait_txbuf_drained().await
+    }
+
     pub fn read(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
         self.bridge.read(cs, buf)
     }
 
     pub fn n_read(&self, cs: CriticalSection) -> usize {
-        self.bridge.read(cs, &mut []).unwrap_err()
+        match self.bridge.read(cs, &mut []) {
+            Ok(n) | Err(n) => n,
+        }
+    }
+
+    /// Moves as many received bytes as fit into `buf` and returns their number.
+    ///
+    /// Unlike [`I2CSlave::read`] the rest stays in the receive buffer for the next call, which
+    /// suits parsing a write frame by frame.
+    pub fn read_some(&self, cs: CriticalSection, buf: &mut [u8]) -> usize {
+        self.bridge.read_some(cs, buf)
+    }
+
+    /// Like [`I2CSlave::read`], for protocols ending each write with a checksum byte.
+    ///
+    /// The payload goes into `buf` and the last byte is returned next to its length, `None`
+    /// means nothing was received. The error is the payload size `buf` has to fit. With
+    /// [`Config::pec`] the hardware has already checked the trailing byte as the PEC.
+    pub fn read_with_crc(
+        &self,
+        cs: CriticalSection,
+        buf: &mut [u8],
+    ) -> Result<Option<(usize, u8)>, usize> {
+        self.bridge.read_with_crc(cs, buf)
+    }
+
+    /// Copies the data of the last `Received` into `buf`, with the same return value as
+    /// [`I2CSlave::read`].
+    ///
+    /// The copy is taken when `Received` is emitted and is kept until the next one, so reading
+    /// or resetting the receive buffer does not affect it. This is what a register read needs
+    /// in the following `TxEmpty`: the index written right before the repeated start.
+    pub fn last_write(&self, cs: CriticalSection, buf: &mut [u8]) -> Result<usize, usize> {
+        self.bridge.last_write(cs, buf)
+    }
+
+    /// Copies the oldest queued write into `buf` and drops it from the queue, with the same
+    /// return value as [`I2CSlave::read`]. Returns `None` if no write is queued.
+    ///
+    /// Writes are only queued with [`Bridge::with_frames`](crate::Bridge::with_frames), which
+    /// also describes how. Writes without data are not queued.
+    #[cfg(feature = "frames")]
+    pub fn read_frame(&self, cs: CriticalSection, buf: &mut [u8]) -> Option<Result<usize, usize>> {
+        self.bridge.read_frame(cs, buf)
+    }
+
+    /// Returns `true` while the slave is taking part in a transaction.
+    ///
+    /// This is the software state kept by the interrupt handler: it leaves `Idle` at the
+    /// address match and returns to it at STOP, so the receive buffer is complete whenever
+    /// this returns `false`. See [`I2CSlave::is_bus_busy`] for the hardware view.
+    pub fn is_busy(&self) -> bool {
+        !matches!(self.bridge.state(), State::Idle)
+    }
+
+    /// Returns the hardware BUSY flag.
+    ///
+    /// Unlike [`I2CSlave::is_busy`] it is set for any communication on the bus, including
+    /// transfers addressed to other devices, from START until STOP.
+    pub fn is_bus_busy(&self) -> bool {
+        T::regs().sr2().read().busy()
+    }
+
+    /// Reads the status bits of SR2 in one go.
+    ///
+    /// Reading SR2 right after SR1 clears ADDR. The interrupt handler always takes both reads
+    /// back to back and the read here is done in a critical section, so it cannot land in
+    /// between, but a read of SR1 anywhere else in the application may have this clear a
+    /// pending address match. `tra`, `gencall` and `dualf` belong to the current transfer, the
+    /// hardware clears them at STOP and at a repeated start.
+    pub fn hw_status(&self) -> HwStatus {
+        let sr2 = critical_section::with(|_| T::regs().sr2().read());
+
+        HwStatus {
+            busy: sr2.busy(),
+            msl: sr2.msl(),
+            tra: sr2.tra(),
+            gencall: sr2.gencall(),
+            dualf: sr2.dualf(),
+        }
+    }
+
+    /// Returns `false` once the peripheral has been disabled, which `fail` does with
+    /// [`Config::disable_on_error`](crate::Config::disable_on_error).
+    pub fn is_enabled(&self) -> bool {
+        T::regs().cr1().read().pe()
+    }
+
+    /// Masks the interrupts of the peripheral until the returned guard is dropped.
+    ///
+    /// Unlike masking them in the NVIC, the flags raised meanwhile are handled right after
+    /// the guard is dropped instead of being left pending with the interrupt disabled. The
+    /// slave stretches SCL in the meantime, with [`Config::no_stretch`] bytes may be lost.
+    /// Keep it short.
+    pub fn mask_guard(&self) -> MaskGuard<T> {
+        MaskGuard::new()
+    }
+
+    /// The last `Error` sent to the consumer, kept until [`I2CSlave::rearm`] or
+    /// [`I2CSlave::hard_reset`], for a supervisor that may have missed it on the channel.
+    pub fn last_error(&self) -> Option<Error> {
+        self.bridge.last_error()
+    }
+
+    /// Brings the peripheral back up after an `Error` without touching the pins and clocks.
+    ///
+    /// Pending status flags are cleared and the state and both buffers are reset, so anything
+    /// from the failed transaction is lost. Calling it on a running slave just resets it.
+    pub fn rearm(&self) {
+        let regs = T::regs();
+
+        regs.cr1().modify(|w| w.set_pe(false));
+
+        critical_section::with(|cs| self.bridge.reset(cs));
+        regs.cr2().modify(|w| w.set_iterren(true));
+
+        regs.sr1().modify(|w| {
+            w.set_berr(false);
+            w.set_arlo(false);
+            w.set_af(false);
+            w.set_ovr(false);
+            w.set_pecerr(false);
+            w.set_timeout(false);
+            w.set_alert(false);
+        });
+
+        regs.cr1().modify(|w| {
+            w.set_engc(true);
+            w.set_ack(self.bridge.is_responsive());
+            w.set_pos(false);
+            w.set_pe(true);
+        });
+    }
+
+    /// Resets the peripheral with SWRST and configures it again from scratch.
+    ///
+    /// Unlike [`I2CSlave::rearm`] this also clears the internal state of the peripheral, which
+    /// is the documented way out of a BUSY flag stuck after a glitch on the bus. The state
+    /// and both buffers are reset as well.
+    pub fn hard_reset(&self) {
+        let regs = T::regs();
+
+        regs.cr1().modify(|w| w.set_pe(false));
+        regs.cr1().modify(|w| w.set_swrst(true));
+        regs.cr1().modify(|w| w.set_swrst(false));
+
+        critical_section::with(|cs| self.bridge.reset(cs));
+
+        self.configure();
+    }
+
+    /// Drops every queued event, and with `history` the event history, returns how many events
+    /// were dropped.
+    ///
+    /// Meant for after [`I2CSlave::rearm`], so events from before the error are not taken for
+    /// new ones.
+    pub fn flush_events(&self) -> usize {
+        let mut n = 0;
+        while self.bridge.try_receive().is_some() {
+            n += 1;
+        }
+
+        #[cfg(feature = "history")]
+        critical_section::with(|cs| self.bridge.clear_history(cs));
+
+        n
     }
 
     pub async fn listen(&self) -> Result<Event, Error> {
         self.bridge.receive().await
     }
+
+    /// Waits for the next `Notification::Stop`, or returns the first `Error`.
+    ///
+    /// Every event up to the STOP is consumed, so a later [`I2CSlave::listen`] does not see
+    /// them. Called while the bus is idle it waits for the end of the next transaction.
+    ///
+    /// The events that hold the bus are answered so the STOP can come. A read running out of
+    /// data gets `0xFF` for every `TxEmpty` and `TxByte`, after what was already in the send
+    /// buffer. Writes are acknowledged with [`I2CSlave::ack_processed`] and stay in the receive
+    /// buffer.
+    pub async fn wait_stop(&self) -> Result<(), Error> {
+        loop {
+            match self.listen().await? {
+                Event::Notification(Notification::Stop) => return Ok(()),
+                Event::Control(Control::TxEmpty { .. } | Control::TxByte) => {
+                    self.write(&[0xFF]);
+                }
+                Event::Control(
+                    Control::Received { .. }
+                    | Control::ReceivedInline { .. }
+                    | Control::GeneralCall { .. },
+                ) => self.ack_processed(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits for a write ended by a STOP and returns its payload in `buf`.
+    ///
+    /// Other events are consumed and ignored, so reads are not served: meant for devices the
+    /// master only writes to. A write followed by a read with repeated start is dropped. Bytes
+    /// that do not fit into `buf` are dropped as well, the returned size counts the stored
+    /// ones. Any `Error` is returned right away.
+    pub async fn read_transaction(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut n = 0;
+        let mut received = false;
+
+        loop {
+            match self.listen().await? {
+                Event::Control(Control::Received { write, .. }) => {
+                    critical_section::with(|cs| {
+                        if write {
+                            n = 0;
+                        } else {
+                            n += self.read_some(cs, &mut buf[n..]);
+                        }
+
+                        while self.read_some(cs, &mut [0u8; 8]) != 0 {}
+                    });
+
+                    received = !write;
+                    self.ack_processed();
+                }
+                Event::Notification(Notification::Stop) if received => return Ok(n),
+                _ => {}
+            }
+        }
+    }
+
+    /// Serves reads until one ends with a STOP, returns the number of bytes the master read.
+    ///
+    /// On every `TxEmpty` `responder` gets the number of bytes already queued for the current
+    /// read and returns the next chunk, an empty chunk is answered with `0xFF`. Writes, like
+    /// the index in front of a register read, are dropped from the receive buffer and
+    /// acknowledged with [`I2CSlave::ack_processed`], the last one can still be taken with
+    /// [`I2CSlave::last_write`]. Other events are consumed and ignored, [`I2CSlave::listen`]
+    /// is still there for anything more involved. Any `Error` is returned right away.
+    pub async fn serve_read<'r>(
+        &self,
+        mut responder: impl FnMut(usize) -> &'r [u8],
+    ) -> Result<usize, Error> {
+        let mut queued = 0;
+        let mut sent = None;
+
+        loop {
+            match self.listen().await? {
+                Event::Control(Control::TxEmpty { initial, .. }) => {
+                    if initial {
+                        queued = 0;
+                    }
+
+                    let chunk = match responder(queued) {
+                        [] => &[0xFF][..],
+                        chunk => chunk,
+                    };
+                    queued += chunk.len() - self.write(chunk).len();
+                }
+                Event::Control(
+                    Control::Received { .. }
+                    | Control::ReceivedInline { .. }
+                    | Control::GeneralCall { .. },
+                ) => {
+                    critical_section::with(|cs| while self.read_some(cs, &mut [0u8; 8]) != 0 {});
+                    self.ack_processed();
+                }
+                Event::Notification(Notification::Sent { sent: n }) => sent = Some(n),
+                Event::Notification(Notification::Stop) => {
+                    if let Some(n) = sent {
+                        return Ok(n);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The events as a [`Stream`](futures_core::Stream), an alternative to calling
+    /// [`I2CSlave::listen`] in a loop.
+    #[cfg(feature = "stream")]
+    pub fn events(&self) -> crate::EventStream<'_, T, B> {
+        crate::EventStream::new(self.bridge)
+    }
+
+    /// Waits for the next event without an async executor.
+    ///
+    /// The core sleeps in WFI between interrupts. The channel is checked with interrupts
+    /// masked, so an event arriving right before WFI still wakes the core.
+    #[cfg(feature = "blocking")]
+    #[allow(clippy::result_large_err)] // Same result as `listen`, large only with `dump`.
+    pub fn listen_blocking(&self) -> Result<Event, Error> {
+        loop {
+            let res = cortex_m::interrupt::free(|_| {
+                let res = self.bridge.try_receive();
+                if res.is_none() {
+                    cortex_m::asm::wfi();
+                }
+                res
+            });
+
+            if let Some(res) = res {
+                return res;
+            }
+        }
+    }
+
+    /// Drives the SMBA line low to request attention from the host.
+    ///
+    /// The SMBA pin is not configured by the crate and must be set up as an open-drain
+    /// alternate function output. While the alert is asserted the peripheral also ACKs the
+    /// Alert Response Address (0b0001100), which the host reads to find out which device
+    /// raised the alert. The interrupt handler answers it with the own address, releases
+    /// SMBA and emits `Notification::AlertResponse`. The peripheral cannot tell the ARA
+    /// apart from the own address, so the first read while the alert is asserted is always
+    /// answered this way.
+    #[cfg(feature = "smbus")]
+    pub fn assert_smbus_alert(&self) {
+        T::regs().cr1().modify(|w| w.set_alert(true));
+    }
+
+    /// Releases the SMBA line. The Alert Response Address is NACKed again afterwards.
+    #[cfg(feature = "smbus")]
+    pub fn deassert_smbus_alert(&self) {
+        T::regs().cr1().modify(|w| w.set_alert(false));
+    }
+}
+
+/// Restores the interrupt enable bits cleared by [`I2CSlave::mask_guard`] when dropped.
+///
+/// A bit set again while masked, like by a write unlocking the send buffer, is kept set.
+#[must_use]
+pub struct MaskGuard<T: i2c::Instance> {
+    itevten: bool,
+    iterren: bool,
+    itbufen: bool,
+    _marker: PhantomData<T>,
+}
+
+impl<T: i2c::Instance> MaskGuard<T> {
+    fn new() -> Self {
+        critical_section::with(|_| {
+            let cr2 = T::regs().cr2().read();
+
+            T::regs().cr2().modify(|w| {
+                w.set_itevten(false);
+                w.set_iterren(false);
+                w.set_itbufen(false);
+            });
+
+            Self {
+                itevten: cr2.itevten(),
+                iterren: cr2.iterren(),
+                itbufen: cr2.itbufen(),
+                _marker: PhantomData,
+            }
+        })
+    }
+}
+
+impl<T: i2c::Instance> Drop for MaskGuard<T> {
+    fn drop(&mut self) {
+        critical_section::with(|_| {
+            T::regs().cr2().modify(|w| {
+                w.set_itevten(w.itevten() || self.itevten);
+                w.set_iterren(w.iterren() || self.iterren);
+                w.set_itbufen(w.itbufen() || self.itbufen);
+            })
+        });
+    }
 }
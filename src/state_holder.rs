@@ -1,12 +1,21 @@
+#[cfg(feature = "dump")]
 use core::cell::{Ref, RefCell};
 
 use atomic::{Atomic, Ordering};
+#[cfg(feature = "dump")]
 use critical_section::{CriticalSection, Mutex};
+#[cfg(feature = "dump")]
 use heapless::Deque;
 
 use crate::State;
 
+// The state is accessed with `Relaxed`. The chip is single-core, so the only concurrency is
+// an interrupt preempting thread code, and stores and loads of the state are never torn or
+// reordered against each other. Nothing is published through the state alone: the buffers
+// it goes with are only touched inside critical sections, which act as fences, and events
+// reach the consumer through the channel, which also takes one.
 pub struct StateHolder<const HISTORY_SIZE: usize> {
+    #[cfg(feature = "dump")]
     history: Mutex<RefCell<Deque<State, HISTORY_SIZE>>>,
     state: Atomic<State>,
 }
@@ -14,21 +23,23 @@ pub struct StateHolder<const HISTORY_SIZE: usize> {
 impl<const HISTORY_SIZE: usize> StateHolder<HISTORY_SIZE> {
     pub const fn new() -> Self {
         Self {
+            #[cfg(feature = "dump")]
             history: Mutex::new(RefCell::new(Deque::new())),
             state: Atomic::new(State::Idle),
         }
     }
 
     pub fn set_state(&self, state: State) {
+        #[cfg(feature = "dump")]
         self.add_state_in_history(state);
-        self.state.store(state, Ordering::SeqCst);
+        self.state.store(state, Ordering::Relaxed);
     }
 
     pub fn get_state(&self) -> State {
-        self.state.load(Ordering::SeqCst)
+        self.state.load(Ordering::Relaxed)
     }
 
-    #[allow(dead_code)]
+    #[cfg(feature = "dump")]
     pub fn get_history<'cs>(
         &'cs self,
         cs: CriticalSection<'cs>,
@@ -36,13 +47,14 @@ impl<const HISTORY_SIZE: usize> StateHolder<HISTORY_SIZE> {
         self.history.borrow_ref(cs)
     }
 
+    #[cfg(feature = "dump")]
     fn add_state_in_history(&self, state: State) {
         critical_section::with(|cs| {
             let mut h = self.history.borrow_ref_mut(cs);
             if h.is_full() {
                 h.pop_front();
             }
-            h.push_back(state).unwrap();
+            let _ = h.push_back(state);
         });
     }
 }
@@ -0,0 +1,35 @@
+use core::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use embassy_stm32::i2c;
+use futures_core::Stream;
+
+use super::{slave::I2cBridge, Error, Event};
+
+/// [`Stream`] of the slave events, see [`I2CSlave::events`](crate::I2CSlave::events).
+///
+/// It never ends, errors are yielded like any other item.
+pub struct EventStream<'a, T: i2c::Instance, B: I2cBridge<T>> {
+    bridge: &'a B,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: i2c::Instance, B: I2cBridge<T>> EventStream<'a, T, B> {
+    pub(crate) fn new(bridge: &'a B) -> Self {
+        Self {
+            bridge,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: i2c::Instance, B: I2cBridge<T>> Stream for EventStream<'a, T, B> {
+    type Item = Result<Event, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.bridge.poll_receive(cx).map(Some)
+    }
+}
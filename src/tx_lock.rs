@@ -5,6 +5,7 @@ use embassy_stm32::i2c;
 pub enum TxLockType {
     TxOnly,
     TxAndBtf,
+    Addr,
 }
 
 pub struct TxLock<T: i2c::Instance> {
@@ -17,15 +18,25 @@ impl<T: i2c::Instance> TxLock<T> {
             _marker: PhantomData,
         }
     }
+}
+
+/// Masks the event interrupt sources that would hand the read over to the interrupt handler
+/// before the consumer has answered, see [`TxLockType`].
+pub trait Lock {
+    fn lock(&self, lock_type: TxLockType);
+
+    fn unlock(&self);
+}
 
-    pub fn lock(&self, lock_type: TxLockType) {
+impl<T: i2c::Instance> Lock for TxLock<T> {
+    fn lock(&self, lock_type: TxLockType) {
         T::regs().cr2().modify(|w| {
             w.set_itbufen(false);
             w.set_itevten(matches!(lock_type, TxLockType::TxOnly));
         });
     }
 
-    pub fn unlock(&self) {
+    fn unlock(&self) {
         T::regs().cr2().modify(|w| {
             w.set_itbufen(true);
             w.set_itevten(true);